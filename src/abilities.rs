@@ -0,0 +1,227 @@
+use serde::Serialize;
+use strum::{Display, EnumIter, IntoEnumIterator};
+
+use crate::ctx::Ctx;
+use crate::dnd::{Ability, AbilityScores, Dice, DiceExpr, DicePool, Keep};
+use crate::history::RollRecord;
+
+use crate::prelude::*;
+
+const POINT_BUY_BUDGET: u8 = 27;
+const STANDARD_ARRAY: [u8; 6] = [15, 14, 13, 12, 10, 8];
+/// Gives up on the house rule after this many sets, so a silly threshold
+/// can't keep us rolling forever.
+const MAX_REROLLS: u32 = 100;
+
+#[derive(Debug, Default, clap::Args)]
+pub struct AbilitiesArgs {
+    /// How to generate the scores
+    #[arg(long, value_enum)]
+    method: Option<GenerationMethod>,
+    /// Reroll the whole set while the scores add up to less than this
+    #[arg(long)]
+    reroll_below: Option<u32>,
+    /// Assign rolled scores in the order they were rolled
+    #[arg(long)]
+    in_order: bool,
+}
+
+#[derive(Debug, Clone, Copy, Display, EnumIter, clap::ValueEnum, Serialize)]
+enum GenerationMethod {
+    #[strum(serialize = "Point buy")]
+    PointBuy,
+    #[strum(serialize = "Standard array")]
+    StandardArray,
+    #[strum(serialize = "Roll 4d6, drop the lowest")]
+    Rolled,
+}
+
+#[derive(Debug, Serialize)]
+struct AbilitiesReport {
+    method: GenerationMethod,
+    /// Every set rolled, including the ones thrown away by the house rule
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    rolls: Vec<Vec<u8>>,
+    abilities: AbilityScores,
+}
+
+pub fn generate_abilities(ctx: &mut Ctx, args: AbilitiesArgs) -> anyhow::Result<()> {
+    let complete = args.method.is_some();
+    let method = args
+        .method
+        .unwrap_or_else(|| select("Choose a method:", GenerationMethod::iter().collect()));
+
+    let mut rolls = vec![];
+    let abilities = match method {
+        GenerationMethod::PointBuy => point_buy(),
+        GenerationMethod::StandardArray => assign(STANDARD_ARRAY.to_vec()),
+        GenerationMethod::Rolled => {
+            let reroll_below = args.reroll_below.or_else(|| {
+                (!complete && confirm("Reroll the set if the scores add up to less than 70?"))
+                    .then_some(70)
+            });
+            rolls = roll_sets(ctx, reroll_below.unwrap_or(0));
+            let scores = rolls.last().cloned().unwrap_or_default();
+            match args.in_order {
+                true => in_order(&scores),
+                false => {
+                    tracing::info!("You rolled {:?}", scores);
+                    assign(scores)
+                }
+            }
+        }
+    };
+    ctx.abilities = Some(abilities);
+
+    let report = AbilitiesReport {
+        method,
+        rolls,
+        abilities,
+    };
+    ctx.output(&report, |report| {
+        for set in &report.rolls[..report.rolls.len().saturating_sub(1)] {
+            tracing::info!("Rerolled {:?}", set);
+        }
+        println!("{}", report.abilities);
+        tracing::info!(
+            "Pass these to other tools with --abilities {}",
+            Ability::iter()
+                .map(|ability| report.abilities.score(ability).to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+    })
+}
+
+/// What a score costs under the point buy rules, if it can be bought at all.
+fn point_cost(score: u8) -> Option<u8> {
+    match score {
+        8..=13 => Some(score - 8),
+        14 => Some(7),
+        15 => Some(9),
+        _ => None,
+    }
+}
+
+fn point_buy() -> AbilityScores {
+    let mut abilities = AbilityScores::default();
+    let mut remaining = POINT_BUY_BUDGET;
+    for ability in Ability::iter() {
+        let options = (8..=15)
+            .filter_map(|score| point_cost(score).map(|cost| (score, cost)))
+            .filter(|(_, cost)| *cost <= remaining)
+            .map(|(score, cost)| PointBuyOption { score, cost })
+            .collect();
+        let choice = select(
+            &format!("{} ({} points remaining):", ability, remaining),
+            options,
+        );
+        remaining -= choice.cost;
+        abilities.set(ability, choice.score);
+    }
+    if remaining > 0 {
+        tracing::info!("{} points left unspent", remaining);
+    }
+    abilities
+}
+
+#[derive(Debug, Clone)]
+struct PointBuyOption {
+    score: u8,
+    cost: u8,
+}
+
+impl std::fmt::Display for PointBuyOption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({} points)", self.score, self.cost)
+    }
+}
+
+/// Lets the user hand out `scores` one ability at a time.
+fn assign(mut scores: Vec<u8>) -> AbilityScores {
+    let mut abilities = AbilityScores::default();
+    for ability in Ability::iter() {
+        let score = match scores.len() {
+            1 => scores[0],
+            _ => select(&format!("{}:", ability), scores.clone()),
+        };
+        if let Some(index) = scores.iter().position(|s| *s == score) {
+            scores.remove(index);
+        }
+        abilities.set(ability, score);
+    }
+    abilities
+}
+
+fn in_order(scores: &[u8]) -> AbilityScores {
+    let mut abilities = AbilityScores::default();
+    for (ability, score) in Ability::iter().zip(scores) {
+        abilities.set(ability, *score);
+    }
+    abilities
+}
+
+/// Rolls sets of six scores until one adds up to at least `reroll_below`.
+/// The last set is the one to keep.
+fn roll_sets(ctx: &mut Ctx, reroll_below: u32) -> Vec<Vec<u8>> {
+    let expr = DiceExpr::Roll(DicePool::new(4, Dice::D6).keep(Keep::Highest(3)));
+    let mut sets = vec![];
+    loop {
+        let set = (0..6)
+            .map(|_| {
+                let result = expr.roll(&mut ctx.rng);
+                ctx.record(RollRecord::from_roll("Ability score", &expr, &result));
+                result.total as u8
+            })
+            .collect::<Vec<_>>();
+        let total = set.iter().map(|score| u32::from(*score)).sum::<u32>();
+        sets.push(set);
+        if total >= reroll_below {
+            break;
+        }
+        if sets.len() as u32 >= MAX_REROLLS {
+            tracing::warn!(
+                "Still below {} after {} sets, keeping the last one",
+                reroll_below,
+                MAX_REROLLS
+            );
+            break;
+        }
+    }
+    sets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test_case]
+    fn test_point_buy_costs() -> TResult {
+        test(|| {
+            let costs = (7..=16).map(point_cost).collect::<Vec<_>>();
+            assert_eq!(
+                costs,
+                vec![
+                    None,
+                    Some(0),
+                    Some(1),
+                    Some(2),
+                    Some(3),
+                    Some(4),
+                    Some(5),
+                    Some(7),
+                    Some(9),
+                    None
+                ]
+            );
+            // the standard array is exactly what 27 points buys
+            let standard = STANDARD_ARRAY
+                .iter()
+                .filter_map(|s| point_cost(*s))
+                .sum::<u8>();
+            assert_eq!(standard, POINT_BUY_BUDGET);
+            costs
+        })
+    }
+}
@@ -1,49 +1,252 @@
 use strum::IntoEnumIterator;
 
+use serde::Serialize;
+
 use crate::{
-    dnd::{Ac, Armor, Shield, UnarmoredDefense},
+    ctx::Ctx,
+    dnd::{Ability, AbilityScores, Ac, AcBonus, AcBreakdown, AcFormula, Armor, Shield},
     prelude::*,
 };
 
-pub(crate) fn calculate_ac() -> Result<(), Box<dyn std::error::Error>> {
-    let armor = select("What armor are you wearing?", Armor::iter().collect());
-    let dex = input_map("What is your dex modifier?", str::parse::<u8>);
-    let unarmored_defense = if let Armor::NoArmor = armor {
-        let class = select(
-            "Are you a Barbarian or Monk?",
-            vec!["Barbarian", "Monk", "Neither"],
-        );
-        match class {
-            "Barbarian" => {
-                let con = input_map("What is your Constitution modifier?", str::parse::<u8>);
-                UnarmoredDefense::Barbarian(con)
-            }
-            "Monk" => {
-                let wisdom = input_map("What is your Wisdom modifier?", str::parse::<u8>);
-                UnarmoredDefense::Monk(wisdom)
-            }
-            "Neither" => UnarmoredDefense::None,
-            _ => unreachable!("Invalid class"),
-        }
-    } else {
-        UnarmoredDefense::None
+#[derive(Debug, Default, clap::Args)]
+pub struct AcArgs {
+    /// Use a saved character instead of the other options
+    #[arg(long)]
+    character: Option<String>,
+    /// The armor you are wearing, e.g. `plate` or `studded-leather`
+    #[arg(long)]
+    armor: Option<Armor>,
+    /// Your ability scores in STR, DEX, CON, INT, WIS, CHA order, e.g. `15,14,13,12,10,8`
+    #[arg(long)]
+    abilities: Option<AbilityScores>,
+    /// Your Dexterity modifier
+    #[arg(
+        long,
+        allow_negative_numbers = true,
+        value_parser = clap::value_parser!(i8).range(-5..=10)
+    )]
+    dex: Option<i8>,
+    /// Your Strength score, e.g. `15`, for heavy armor's requirement; a modifier
+    /// can't tell 14 from 15
+    #[arg(long = "str-score", value_parser = clap::value_parser!(u8).range(1..=30))]
+    strength: Option<u8>,
+    /// You are using a shield
+    #[arg(long)]
+    shield: bool,
+    /// A magic item or feature that adds to your AC, e.g. `armor+1`, `shield+2`,
+    /// `ring-of-protection` or `defense`; repeat it for each
+    #[arg(long)]
+    bonus: Vec<AcBonus>,
+    /// Another way to work out your AC if you wear no armor, e.g. `barbarian`, `monk`,
+    /// `mage-armor`, `tortle` or a homebrew `12+con`; repeat it for each
+    #[arg(long)]
+    formula: Vec<AcFormula>,
+    /// Your Constitution modifier, for Barbarian Unarmored Defense
+    #[arg(
+        long,
+        allow_negative_numbers = true,
+        value_parser = clap::value_parser!(i8).range(-5..=10)
+    )]
+    con: Option<i8>,
+    /// Your Intelligence modifier, for Bladesong
+    #[arg(
+        long,
+        allow_negative_numbers = true,
+        value_parser = clap::value_parser!(i8).range(-5..=10)
+    )]
+    int: Option<i8>,
+    /// Your Wisdom modifier, for Monk Unarmored Defense
+    #[arg(
+        long,
+        allow_negative_numbers = true,
+        value_parser = clap::value_parser!(i8).range(-5..=10)
+    )]
+    wis: Option<i8>,
+}
+
+#[derive(Debug, Serialize)]
+struct AcReport {
+    armor: Armor,
+    abilities: AbilityScores,
+    shield: Shield,
+    /// Every formula the character has besides wearing armor
+    formulas: Vec<AcFormula>,
+    breakdown: AcBreakdown,
+    /// The bonuses that don't, like Bracers of Defense worn with armor
+    ignored: Vec<AcBonus>,
+    /// Too weak for the armor, losing 10 feet of speed; unknown if STR wasn't given
+    speed_penalty: Option<bool>,
+    stealth_disadvantage: bool,
+    ac: u8,
+}
+
+/// The formulas that can apply with `armor`, the armor's own one included.
+fn usable_formulas(armor: Armor, formulas: &[AcFormula]) -> impl Iterator<Item = AcFormula> + '_ {
+    std::iter::once(AcFormula::Armor)
+        .chain(formulas.iter().copied())
+        .filter(move |formula| formula.allows(armor, Shield::NoShield))
+}
+
+/// Heavy armor ignores DEX, so there's no point asking for it.
+fn needs_dex(armor: Armor, formulas: &[AcFormula]) -> bool {
+    usable_formulas(armor, formulas).any(|formula| formula.max_dex(armor) != Some(0))
+}
+
+pub(crate) fn calculate_ac(ctx: &mut Ctx, args: AcArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let given = args.armor.is_some_and(|armor| {
+        args.abilities.is_some() || args.dex.is_some() || !needs_dex(armor, &args.formula)
+    });
+    let character = ctx.character(args.character.as_deref(), !given)?;
+    // the shield question is only asked if we had to prompt for something else
+    let mut complete = character.is_some() || args.armor.is_some();
+
+    let armor = args
+        .armor
+        .or_else(|| character.as_ref().map(|character| character.armor))
+        .unwrap_or_else(|| select("What armor are you wearing?", Armor::iter().collect()));
+    let known = ctx.abilities(
+        args.abilities
+            .or_else(|| character.as_ref().map(|character| character.abilities)),
+    );
+    let mut abilities = known.unwrap_or_default();
+    let flag = |ability| match ability {
+        Ability::Dexterity => args.dex,
+        Ability::Constitution => args.con,
+        Ability::Intelligence => args.int,
+        Ability::Wisdom => args.wis,
+        _ => None,
+    };
+    // flags override the ability scores, which in turn mean we don't have to ask
+    let mut ability = |ability| match (known, flag(ability)) {
+        (Some(_), None) => true,
+        (_, modifier) => abilities.set_or_prompt(ability, modifier),
     };
-    let shield = confirm("Are you using a shield?");
+
+    let formulas = match (args.formula.is_empty(), &character) {
+        (false, _) => args.formula,
+        (true, Some(character)) => character.ac_formulas(),
+        // leaving out `--formula` with `--armor` means there aren't any others
+        (true, None) if armor == Armor::NoArmor && !complete => AcFormula::prompt_many(),
+        (true, None) => vec![],
+    };
+    if needs_dex(armor, &formulas) {
+        complete &= ability(Ability::Dexterity);
+    }
+    for formula_ability in usable_formulas(armor, &formulas).filter_map(|formula| formula.ability())
+    {
+        complete &= ability(formula_ability);
+    }
+
+    let shield = args.shield
+        || character.as_ref().is_some_and(|character| character.shield)
+        || !complete && confirm("Are you using a shield?");
     let shield = match shield {
         true => Shield::Shield,
         false => Shield::NoShield,
     };
 
-    let ac = Ac(armor, dex, shield, unarmored_defense);
-    let result = ac.calculate();
-
-    tracing::info!("Your AC is {}", result);
-    if let (Shield::Shield, UnarmoredDefense::Monk(ability)) = (shield, unarmored_defense) {
-        tracing::info!("Tip: Monks lose their Unarmored Defense when using a shield");
-        if ability > 2 {
-            tracing::info!("You are losing out on {} AC by using a shield", ability - 2)
+    let bonuses = match (args.bonus.is_empty(), &character) {
+        (false, _) => args.bonus,
+        (true, Some(character)) => character.ac_bonuses.clone(),
+        (true, None) if !complete => AcBonus::prompt_many(armor, shield),
+        (true, None) => vec![],
+    };
+    if bonuses.contains(&AcBonus::Bladesong) {
+        ability(Ability::Intelligence);
+    }
+    // STR is only asked for along with everything else, never on its own
+    let strength_known = match (args.strength, known) {
+        _ if armor.strength_requirement().is_none() => false,
+        (Some(score), _) => {
+            abilities.set(Ability::Strength, score);
+            true
+        }
+        (None, Some(_)) => true,
+        (None, None) if !complete => {
+            abilities.prompt(Ability::Strength);
+            true
         }
+        (None, None) => false,
+    };
+
+    let ignored = bonuses
+        .iter()
+        .copied()
+        .filter(|bonus| !bonus.applies(armor, shield))
+        .collect();
+    let ac = Ac {
+        armor,
+        abilities,
+        shield,
+        formulas: formulas.clone(),
+        bonuses,
+    };
+    let breakdown = ac.breakdown();
+    // what Unarmored Defense would give without the shield
+    let without_shield = match shield {
+        Shield::Shield => Some(
+            Ac {
+                shield: Shield::NoShield,
+                ..ac
+            }
+            .breakdown(),
+        ),
+        Shield::NoShield => None,
     }
+    .filter(|breakdown| breakdown.formula == AcFormula::Monk);
+    let report = AcReport {
+        armor,
+        abilities,
+        shield,
+        formulas,
+        ac: breakdown.total,
+        breakdown,
+        ignored,
+        speed_penalty: strength_known
+            .then(|| armor.speed_penalty(abilities.score(Ability::Strength))),
+        stealth_disadvantage: armor.stealth_disadvantage(),
+    };
+
+    ctx.output(&report, |report| {
+        tracing::info!("Your AC is {}", report.ac);
+        println!("{}", report.breakdown);
+        for bonus in &report.ignored {
+            tracing::warn!("{} doesn't count with your armor and shield", bonus);
+        }
+        if report.armor != Armor::NoArmor {
+            tracing::info!(
+                "{} weighs {} lb and costs {} gp",
+                report.armor,
+                report.armor.weight(),
+                report.armor.cost()
+            );
+        }
+        match (report.speed_penalty, armor.strength_requirement()) {
+            (Some(true), Some(required)) => tracing::warn!(
+                "{} needs {} STR, without it your speed is reduced by 10 feet",
+                report.armor,
+                required
+            ),
+            (None, Some(required)) => tracing::warn!(
+                "{} needs {} STR, or your speed is reduced by 10 feet",
+                report.armor,
+                required
+            ),
+            _ => {}
+        }
+        if report.stealth_disadvantage {
+            tracing::warn!("{} gives you disadvantage on Stealth checks", report.armor);
+        }
+        if let Some(without_shield) = &without_shield {
+            tracing::info!("Tip: Monks lose their Unarmored Defense when using a shield");
+            if without_shield.total > report.ac {
+                tracing::info!(
+                    "You are losing out on {} AC by using a shield",
+                    without_shield.total - report.ac
+                )
+            }
+        }
+    })?;
 
     Ok(())
 }
@@ -0,0 +1,424 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumIter, IntoEnumIterator};
+
+use crate::ctx::Ctx;
+use crate::dnd::{
+    format_classes, Ability, AbilityScores, AcBonus, AcFormula, Armor, Class, ClassLevels,
+    Defenses, Shield,
+};
+use crate::hp::{max_hp, record_rolls, Hp, HpModifier, HpTracker, LevelHp, Method};
+
+use crate::prelude::*;
+
+#[derive(Debug, Default, clap::Args)]
+pub struct CharacterArgs {
+    #[command(subcommand)]
+    action: Option<CharacterAction>,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum CharacterAction {
+    /// Create a new character, or overwrite one with the same name
+    Create,
+    /// List the saved characters
+    List,
+    /// Show a saved character
+    Show { name: String },
+    /// Delete a saved character
+    Delete { name: String },
+}
+
+pub fn manage_characters(ctx: &mut Ctx, args: CharacterArgs) -> anyhow::Result<()> {
+    let action = match args.action {
+        Some(action) => action,
+        None => {
+            let action = select("What would you like to do?", Action::iter().collect());
+            match action {
+                Action::Create => CharacterAction::Create,
+                Action::List => CharacterAction::List,
+                Action::Show | Action::Delete => {
+                    let characters = ctx.characters.list()?;
+                    if characters.is_empty() {
+                        return ctx.output(&characters, |_| tracing::info!("No saved characters"));
+                    }
+                    let name = select("Which character?", characters).name;
+                    match action {
+                        Action::Delete => CharacterAction::Delete { name },
+                        _ => CharacterAction::Show { name },
+                    }
+                }
+            }
+        }
+    };
+
+    match action {
+        CharacterAction::Create => {
+            let character = Character::prompt(ctx);
+            ctx.characters.save(&character)?;
+            ctx.output(&character, |character| {
+                tracing::info!("Saved {}", character)
+            })?;
+        }
+        CharacterAction::List => {
+            let characters = ctx.characters.list()?;
+            ctx.output(&characters, |characters| match characters.is_empty() {
+                true => tracing::info!("No saved characters"),
+                false => {
+                    for character in characters {
+                        println!("{}", character);
+                    }
+                }
+            })?;
+        }
+        CharacterAction::Show { name } => {
+            let character = ctx.characters.load(&name)?;
+            ctx.output(&character, |character| {
+                println!("{}", character);
+                println!("{}", character.abilities);
+                match character.shield {
+                    true => println!("{} and a shield", character.armor),
+                    false => println!("{}", character.armor),
+                }
+                for formula in &character.ac_formulas {
+                    println!("{}", formula);
+                }
+                for bonus in &character.ac_bonuses {
+                    println!("{}", bonus);
+                }
+                for modifier in &character.hp_modifiers {
+                    println!("{}", modifier);
+                }
+                if !character.defenses.is_empty() {
+                    println!("{}", character.defenses);
+                }
+                if !character.hp.is_empty() {
+                    println!("Max HP: {}", max_hp(&character.hp));
+                }
+            })?;
+        }
+        CharacterAction::Delete { name } => {
+            ctx.characters.delete(&name)?;
+            tracing::info!("Deleted {}", name);
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Display, EnumIter, Clone)]
+enum Action {
+    #[strum(serialize = "Create a character")]
+    Create,
+    #[strum(serialize = "List characters")]
+    List,
+    #[strum(serialize = "Show a character")]
+    Show,
+    #[strum(serialize = "Delete a character")]
+    Delete,
+}
+
+/// Everything the tools would otherwise ask about, saved under a name.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Character {
+    pub name: String,
+    pub classes: Vec<ClassLevels>,
+    pub abilities: AbilityScores,
+    pub armor: Armor,
+    pub shield: bool,
+    /// Other ways of working out AC, like Mage Armor or natural armor;
+    /// Unarmored Defense comes from the classes instead
+    #[serde(default)]
+    pub ac_formulas: Vec<AcFormula>,
+    /// Magic items and features that add to AC
+    #[serde(default)]
+    pub ac_bonuses: Vec<AcBonus>,
+    /// Feats, traits and items that add to max HP
+    #[serde(default)]
+    pub hp_modifiers: Vec<HpModifier>,
+    /// What each level gave, in the order they were taken
+    #[serde(default)]
+    pub hp: Vec<LevelHp>,
+    #[serde(default)]
+    pub defenses: Defenses,
+    /// Current HP, once the tracker has been used
+    #[serde(default)]
+    pub tracker: Option<HpTracker>,
+}
+
+impl Character {
+    fn prompt(ctx: &mut Ctx) -> Character {
+        let name = input_map("Character name: ", parse_name);
+        let classes = ClassLevels::prompt();
+        let abilities = ctx.abilities(None).unwrap_or_else(|| {
+            let mut abilities = AbilityScores::default();
+            for ability in Ability::iter() {
+                abilities.prompt(ability);
+            }
+            abilities
+        });
+        let armor = select("What armor are you wearing?", Armor::iter().collect());
+        let shield = confirm("Are you using a shield?");
+        let ac_formulas = match armor {
+            Armor::NoArmor => AcFormula::prompt_many(),
+            _ => vec![],
+        };
+        let ac_bonuses = AcBonus::prompt_many(
+            armor,
+            match shield {
+                true => Shield::Shield,
+                false => Shield::NoShield,
+            },
+        );
+        let hp_modifiers = HpModifier::prompt_many();
+        let defenses = match confirm("Any damage resistances, vulnerabilities or immunities?") {
+            true => Defenses::prompt(),
+            false => Defenses::default(),
+        };
+        let mut character = Character {
+            name,
+            classes,
+            abilities,
+            armor,
+            shield,
+            ac_formulas,
+            ac_bonuses,
+            hp_modifiers,
+            defenses,
+            hp: vec![],
+            tracker: None,
+        };
+        let method = select("How do you work out your HP?", Method::iter().collect());
+        character.hp = Hp::from_character(&character, method).levels(&mut ctx.rng);
+        record_rolls(ctx, &character.hp, 1);
+        character
+    }
+
+    /// Unarmored Defense comes from whichever class granted it first; a
+    /// multiclassed character doesn't gain it again from the other one.
+    pub fn unarmored_defense(&self) -> Option<AcFormula> {
+        self.classes.iter().find_map(|class| match class.class {
+            Class::Barbarian => Some(AcFormula::Barbarian),
+            Class::Monk => Some(AcFormula::Monk),
+            _ => None,
+        })
+    }
+
+    /// Every way the character can work out their AC besides wearing armor.
+    pub fn ac_formulas(&self) -> Vec<AcFormula> {
+        let mut formulas: Vec<_> = self.unarmored_defense().into_iter().collect();
+        for formula in &self.ac_formulas {
+            if !formulas.contains(formula) {
+                formulas.push(*formula);
+            }
+        }
+        formulas
+    }
+}
+
+impl std::fmt::Display for Character {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}, {}", self.name, format_classes(&self.classes))
+    }
+}
+
+#[derive(Debug, Clone)]
+enum CharacterChoice {
+    Saved(Box<Character>),
+    Manual,
+}
+
+impl std::fmt::Display for CharacterChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CharacterChoice::Saved(character) => write!(f, "{}", character),
+            CharacterChoice::Manual => write!(f, "Enter everything by hand"),
+        }
+    }
+}
+
+/// One JSON file per character.
+#[derive(Debug, Clone)]
+pub struct CharacterStore {
+    dir: PathBuf,
+}
+
+impl CharacterStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// The characters folder in the user's data directory.
+    pub fn open_default() -> Self {
+        let dir = dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("danjon");
+        Self::new(dir.join("characters"))
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", file_name(name)))
+    }
+
+    pub fn save(&self, character: &Character) -> anyhow::Result<()> {
+        parse_name(&character.name)?;
+        // "Bob!" and "Bob?" share a file, so one mustn't overwrite the other
+        if let Ok(saved) = self.load(&character.name) {
+            if saved.name != character.name {
+                anyhow::bail!(
+                    "'{}' would overwrite the saved character '{}', pick another name",
+                    character.name,
+                    saved.name
+                );
+            }
+        }
+        std::fs::create_dir_all(&self.dir)?;
+        let json = serde_json::to_string_pretty(character)?;
+        std::fs::write(self.path(&character.name), json)?;
+        Ok(())
+    }
+
+    pub fn load(&self, name: &str) -> anyhow::Result<Character> {
+        let json = std::fs::read_to_string(self.path(name)).map_err(|err| match err.kind() {
+            std::io::ErrorKind::NotFound => anyhow::anyhow!("no saved character named '{}'", name),
+            _ => err.into(),
+        })?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    pub fn delete(&self, name: &str) -> anyhow::Result<()> {
+        // loading first gives a friendly error for a character that doesn't exist
+        self.load(name)?;
+        std::fs::remove_file(self.path(name))?;
+        Ok(())
+    }
+
+    pub fn list(&self) -> anyhow::Result<Vec<Character>> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err.into()),
+        };
+        let mut characters = vec![];
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            // one broken or outdated file shouldn't hide every other character
+            let character = std::fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|json| Ok(serde_json::from_str(&json)?));
+            match character {
+                Ok(character) => characters.push(character),
+                Err(err) => tracing::warn!("Skipping {}: {}", path.display(), err),
+            }
+        }
+        characters.sort_by(|a: &Character, b| a.name.cmp(&b.name));
+        Ok(characters)
+    }
+
+    /// Loads the character called `name`, or asks which one to use. Tools
+    /// that only make sense for a saved character use this.
+    pub fn load_or_select(&self, name: Option<&str>, prompt: &str) -> anyhow::Result<Character> {
+        if let Some(name) = name {
+            return self.load(name);
+        }
+        let characters = self.list()?;
+        if characters.is_empty() {
+            anyhow::bail!("there are no saved characters yet, create one first");
+        }
+        Ok(select(prompt, characters))
+    }
+
+    /// Lets the user pick a saved character, or `None` to enter everything
+    /// by hand. Doesn't ask if there is nothing saved.
+    pub fn choose(&self) -> anyhow::Result<Option<Character>> {
+        let characters = self.list()?;
+        if characters.is_empty() {
+            return Ok(None);
+        }
+        let choices = characters
+            .into_iter()
+            .map(|character| CharacterChoice::Saved(Box::new(character)))
+            .chain(std::iter::once(CharacterChoice::Manual))
+            .collect();
+        match select("Use a saved character?", choices) {
+            CharacterChoice::Saved(character) => Ok(Some(*character)),
+            CharacterChoice::Manual => Ok(None),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct EmptyNameError;
+
+impl std::fmt::Display for EmptyNameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "a character needs a name")
+    }
+}
+
+impl std::error::Error for EmptyNameError {}
+
+/// A blank name would be saved as a hidden `.json` that [`CharacterStore::list`] never finds.
+fn parse_name(name: &str) -> Result<String, EmptyNameError> {
+    match name.trim() {
+        "" => Err(EmptyNameError),
+        name => Ok(name.to_string()),
+    }
+}
+
+/// Names are free text, but file names shouldn't be.
+fn file_name(name: &str) -> String {
+    name.trim()
+        .chars()
+        .map(|c| match c.is_alphanumeric() {
+            true => c.to_ascii_lowercase(),
+            false => '-',
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hp::KnownModifier;
+    use pretty_assertions::assert_eq;
+
+    #[test_case]
+    fn test_character_round_trip() -> TResult {
+        test(|| {
+            let character = Character {
+                name: "Bruenor Battlehammer".to_string(),
+                classes: vec![ClassLevels {
+                    class: Class::Fighter,
+                    levels: 5,
+                }],
+                abilities: "16,12,16,10,13,8".parse().unwrap(),
+                armor: Armor::ChainMail,
+                shield: true,
+                ac_formulas: vec![],
+                ac_bonuses: vec![AcBonus::MagicArmor(1)],
+                hp_modifiers: vec![KnownModifier::Tough.modifier()],
+                defenses: Defenses::default(),
+                hp: vec![],
+                tracker: None,
+            };
+            let json = serde_json::to_string(&character).unwrap();
+            assert_eq!(serde_json::from_str::<Character>(&json).unwrap(), character);
+            assert_eq!(file_name(&character.name), "bruenor-battlehammer");
+
+            let dir = std::env::temp_dir().join(format!("danjon-{}", std::process::id()));
+            let store = CharacterStore::new(dir.clone());
+            store.save(&character).unwrap();
+            let mut twin = character.clone();
+            twin.name = "Bruenor-Battlehammer".to_string();
+            assert!(store.save(&twin).is_err());
+            store.save(&character).unwrap();
+            std::fs::remove_dir_all(dir).unwrap();
+            json
+        })
+    }
+}
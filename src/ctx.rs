@@ -0,0 +1,81 @@
+use rand::{rngs::StdRng, SeedableRng};
+use serde::Serialize;
+
+use crate::character::{Character, CharacterStore};
+use crate::dnd::AbilityScores;
+use crate::history::{History, RollRecord};
+use crate::prelude::*;
+
+/// State shared by every tool for the duration of a run.
+///
+/// All dice go through `rng`, so a run started with `--seed` rolls the same
+/// numbers every time.
+pub struct Ctx {
+    pub rng: StdRng,
+    pub history: History,
+    pub characters: CharacterStore,
+    /// Print results as JSON instead of prose
+    pub json: bool,
+    /// The last ability scores generated this run, for other tools to reuse
+    pub abilities: Option<AbilityScores>,
+}
+
+impl Ctx {
+    pub fn new(seed: Option<u64>, json: bool) -> Self {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Self {
+            rng,
+            history: History::open_default(),
+            characters: CharacterStore::open_default(),
+            json,
+            abilities: None,
+        }
+    }
+
+    /// Prints `result` as JSON in `--json` mode, otherwise lets `describe`
+    /// explain it to the user. Each result is one line, so a run that prints
+    /// several, like `roll 1d6 2d6`, can be read line by line.
+    pub fn output<T: Serialize + ?Sized>(
+        &self,
+        result: &T,
+        describe: impl FnOnce(&T),
+    ) -> anyhow::Result<()> {
+        match self.json {
+            true => println!("{}", serde_json::to_string(result)?),
+            false => describe(result),
+        }
+        Ok(())
+    }
+
+    /// Ability scores given on the command line, or the ones generated
+    /// earlier this run if the user wants to reuse them.
+    pub fn abilities(&self, given: Option<AbilityScores>) -> Option<AbilityScores> {
+        given.or_else(|| {
+            self.abilities
+                .filter(|abilities| confirm(&format!("Use your ability scores ({})?", abilities)))
+        })
+    }
+
+    /// The character named on the command line, or, if `offer` is set, one
+    /// the user picks from the saved characters. `None` means fall back to
+    /// prompts.
+    pub fn character(&self, name: Option<&str>, offer: bool) -> anyhow::Result<Option<Character>> {
+        match (name, offer) {
+            (Some(name), _) => self.characters.load(name).map(Some),
+            (None, true) => self.characters.choose(),
+            (None, false) => Ok(None),
+        }
+    }
+
+    /// Appends a roll to the history; a history that can't be written to
+    /// shouldn't stop the roll itself.
+    pub fn record(&self, record: RollRecord) {
+        tracing::debug!(?record);
+        if let Err(err) = self.history.append(&record) {
+            tracing::warn!("Couldn't save roll to history: {}", err);
+        }
+    }
+}
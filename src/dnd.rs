@@ -1,4 +1,4 @@
-use std::{str::FromStr, sync::Arc};
+use std::{collections::BTreeSet, str::FromStr, sync::Arc};
 
 use nom::{
     branch::alt,
@@ -7,11 +7,17 @@ use nom::{
     combinator::{map, map_res},
     sequence::tuple,
 };
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 use strum::{Display, EnumIter, IntoEnumIterator};
 
 use crate::prelude::*;
 
-#[derive(Default, Debug, EnumIter, Display, Clone, PartialEq, Eq, Copy)]
+mod expr;
+
+pub use expr::*;
+
+#[derive(Default, Debug, EnumIter, Display, Clone, PartialEq, Eq, Copy, Serialize, Deserialize)]
 pub enum Dice {
     D4,
     D6,
@@ -26,14 +32,31 @@ pub enum Dice {
 impl Dice {
     pub fn prompt(prompt: &str) -> Dice {
         let dice = select(prompt, Dice::iter().collect());
-        let dice = match dice {
+        match dice {
             Dice::Other(_) => input_map(
                 "Enter the number of sides of the hit dice: ",
                 Dice::from_str,
             ),
             dice => dice,
-        };
-        dice
+        }
+    }
+
+    pub fn roll(&self, rng: &mut impl Rng) -> u8 {
+        rng.gen_range(1..=u8::from(*self))
+    }
+}
+
+impl From<u8> for Dice {
+    fn from(faces: u8) -> Self {
+        match faces {
+            4 => Dice::D4,
+            6 => Dice::D6,
+            8 => Dice::D8,
+            10 => Dice::D10,
+            12 => Dice::D12,
+            20 => Dice::D20,
+            other => Dice::Other(other),
+        }
     }
 }
 
@@ -85,19 +108,11 @@ impl FromStr for Dice {
             )(s)
         }
         let (_, parsed) = parse_dice(s).map_err(|_| DiceParseError)?;
-        match parsed {
-            4 => Ok(Dice::D4),
-            6 => Ok(Dice::D6),
-            8 => Ok(Dice::D8),
-            10 => Ok(Dice::D10),
-            12 => Ok(Dice::D12),
-            20 => Ok(Dice::D20),
-            other => Ok(Dice::Other(other)),
-        }
+        Ok(Dice::from(parsed))
     }
 }
 
-#[derive(EnumIter, Display, Debug, Clone, PartialEq, Eq)]
+#[derive(EnumIter, Display, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Class {
     Barbarian,
     Bard,
@@ -155,7 +170,233 @@ impl Class {
     }
 }
 
-#[derive(Debug, Clone, Copy, EnumIter, Display)]
+impl FromStr for Class {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        from_name(s)
+            .filter(|class| !matches!(class, Class::Homebrew { .. }))
+            .ok_or_else(|| anyhow::anyhow!("unknown class '{}'", s))
+    }
+}
+
+/// Levels taken in one class; a multiclassed character has several.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClassLevels {
+    pub class: Class,
+    pub levels: u32,
+}
+
+impl ClassLevels {
+    /// Asks for a class and its levels, then for any further classes.
+    pub fn prompt() -> Vec<ClassLevels> {
+        let mut classes = vec![];
+        loop {
+            let class = Class::prompt();
+            let levels = input_map(&format!("Levels in {}: ", class), str::parse::<u32>);
+            classes.push(ClassLevels { class, levels });
+            if !confirm("Are you multiclassed into another class?") {
+                return classes;
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for ClassLevels {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.class, self.levels)
+    }
+}
+
+/// `Fighter 2 / Warlock 5`
+pub fn format_classes(classes: &[ClassLevels]) -> String {
+    classes
+        .iter()
+        .map(ClassLevels::to_string)
+        .collect::<Vec<_>>()
+        .join(" / ")
+}
+
+#[derive(Debug, Clone, Copy, EnumIter, Display, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Ability {
+    Strength,
+    Dexterity,
+    Constitution,
+    Intelligence,
+    Wisdom,
+    Charisma,
+}
+
+impl Ability {
+    pub fn abbreviation(&self) -> &'static str {
+        match self {
+            Ability::Strength => "STR",
+            Ability::Dexterity => "DEX",
+            Ability::Constitution => "CON",
+            Ability::Intelligence => "INT",
+            Ability::Wisdom => "WIS",
+            Ability::Charisma => "CHA",
+        }
+    }
+}
+
+/// The modifier a score grants: 10-11 is +0, every 2 points above or below
+/// adds or removes 1.
+pub fn modifier(score: u8) -> i8 {
+    (i16::from(score) - 10).div_euclid(2) as i8
+}
+
+/// The lowest score that grants `modifier`.
+pub fn score_for(modifier: i8) -> u8 {
+    (10 + 2 * i16::from(modifier)).clamp(1, 30) as u8
+}
+
+#[derive(Debug)]
+pub struct AbilityParseError;
+
+impl std::fmt::Display for AbilityParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "enter a score from 1 to 30, or a signed modifier from -5 to +10"
+        )
+    }
+}
+
+impl std::error::Error for AbilityParseError {}
+
+/// Parses either a score (`14`) or a modifier with its sign (`+2`, `-1`),
+/// returning the score.
+pub fn parse_score(s: &str) -> Result<u8, AbilityParseError> {
+    let s = s.trim();
+    if s.starts_with(['+', '-']) {
+        let modifier = s.parse::<i8>().map_err(|_| AbilityParseError)?;
+        if !(-5..=10).contains(&modifier) {
+            return Err(AbilityParseError);
+        }
+        return Ok(score_for(modifier));
+    }
+    match s.parse::<u8>() {
+        Ok(score) if (1..=30).contains(&score) => Ok(score),
+        _ => Err(AbilityParseError),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AbilityScores {
+    pub strength: u8,
+    pub dexterity: u8,
+    pub constitution: u8,
+    pub intelligence: u8,
+    pub wisdom: u8,
+    pub charisma: u8,
+}
+
+impl Default for AbilityScores {
+    fn default() -> Self {
+        Self {
+            strength: 10,
+            dexterity: 10,
+            constitution: 10,
+            intelligence: 10,
+            wisdom: 10,
+            charisma: 10,
+        }
+    }
+}
+
+impl AbilityScores {
+    pub fn score(&self, ability: Ability) -> u8 {
+        match ability {
+            Ability::Strength => self.strength,
+            Ability::Dexterity => self.dexterity,
+            Ability::Constitution => self.constitution,
+            Ability::Intelligence => self.intelligence,
+            Ability::Wisdom => self.wisdom,
+            Ability::Charisma => self.charisma,
+        }
+    }
+
+    pub fn set(&mut self, ability: Ability, score: u8) {
+        let slot = match ability {
+            Ability::Strength => &mut self.strength,
+            Ability::Dexterity => &mut self.dexterity,
+            Ability::Constitution => &mut self.constitution,
+            Ability::Intelligence => &mut self.intelligence,
+            Ability::Wisdom => &mut self.wisdom,
+            Ability::Charisma => &mut self.charisma,
+        };
+        *slot = score;
+    }
+
+    pub fn modifier(&self, ability: Ability) -> i8 {
+        modifier(self.score(ability))
+    }
+
+    /// Asks for a single ability, accepting either a score or a modifier.
+    pub fn prompt(&mut self, ability: Ability) {
+        let score = input_map(
+            &format!("{} score or modifier (e.g. 14 or +2): ", ability),
+            parse_score,
+        );
+        self.set(ability, score);
+    }
+
+    /// Fills in `ability` from a modifier given on the command line, or asks
+    /// for it if there isn't one.
+    pub fn set_or_prompt(&mut self, ability: Ability, modifier: Option<i8>) -> bool {
+        match modifier {
+            Some(modifier) => {
+                self.set(ability, score_for(modifier));
+                true
+            }
+            None => {
+                self.prompt(ability);
+                false
+            }
+        }
+    }
+}
+
+impl FromStr for AbilityScores {
+    type Err = AbilityParseError;
+
+    /// Six scores in STR, DEX, CON, INT, WIS, CHA order, e.g. `15,14,13,12,10,8`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let scores = s
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|score| !score.is_empty())
+            .map(parse_score)
+            .collect::<Result<Vec<_>, _>>()?;
+        if scores.len() != 6 {
+            return Err(AbilityParseError);
+        }
+        let mut abilities = AbilityScores::default();
+        for (ability, score) in Ability::iter().zip(scores) {
+            abilities.set(ability, score);
+        }
+        Ok(abilities)
+    }
+}
+
+impl std::fmt::Display for AbilityScores {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let abilities = Ability::iter()
+            .map(|ability| {
+                format!(
+                    "{} {} ({:+})",
+                    ability.abbreviation(),
+                    self.score(ability),
+                    self.modifier(ability)
+                )
+            })
+            .collect::<Vec<_>>();
+        write!(f, "{}", abilities.join("  "))
+    }
+}
+
+#[derive(Debug, Clone, Copy, EnumIter, Display, PartialEq, Eq, Serialize, Deserialize)]
+#[allow(clippy::enum_variant_names)]
 pub(crate) enum Armor {
     #[strum(serialize = "No armor")]
     NoArmor,
@@ -179,6 +420,96 @@ pub(crate) enum Armor {
     Plate,
 }
 
+impl Armor {
+    /// The most DEX modifier this armor lets through, or `None` if it's uncapped.
+    pub fn max_dex(&self) -> Option<i8> {
+        match self {
+            Armor::NoArmor | Armor::Padded | Armor::Leather | Armor::StuddedLeather => None,
+            Armor::Hide
+            | Armor::ChainShirt
+            | Armor::ScaleMail
+            | Armor::Breastplate
+            | Armor::HalfPlate => Some(2),
+            Armor::RingMail | Armor::ChainMail | Armor::Splint | Armor::Plate => Some(0),
+        }
+    }
+
+    /// The Strength score needed to wear it without slowing down.
+    pub fn strength_requirement(&self) -> Option<u8> {
+        match self {
+            Armor::ChainMail => Some(13),
+            Armor::Splint | Armor::Plate => Some(15),
+            _ => None,
+        }
+    }
+
+    /// Whether a wearer with a Strength score of `strength` has their speed
+    /// reduced by 10 feet.
+    pub fn speed_penalty(&self, strength: u8) -> bool {
+        self.strength_requirement()
+            .is_some_and(|required| strength < required)
+    }
+
+    pub fn stealth_disadvantage(&self) -> bool {
+        matches!(
+            self,
+            Armor::Padded
+                | Armor::ScaleMail
+                | Armor::HalfPlate
+                | Armor::RingMail
+                | Armor::ChainMail
+                | Armor::Splint
+                | Armor::Plate
+        )
+    }
+
+    /// In pounds.
+    pub fn weight(&self) -> u32 {
+        match self {
+            Armor::NoArmor => 0,
+            Armor::Padded => 8,
+            Armor::Leather => 10,
+            Armor::StuddedLeather => 13,
+            Armor::Hide => 12,
+            Armor::ChainShirt => 20,
+            Armor::ScaleMail => 45,
+            Armor::Breastplate => 20,
+            Armor::HalfPlate => 40,
+            Armor::RingMail => 40,
+            Armor::ChainMail => 55,
+            Armor::Splint => 60,
+            Armor::Plate => 65,
+        }
+    }
+
+    /// In gold pieces.
+    pub fn cost(&self) -> u32 {
+        match self {
+            Armor::NoArmor => 0,
+            Armor::Padded => 5,
+            Armor::Leather => 10,
+            Armor::StuddedLeather => 45,
+            Armor::Hide => 10,
+            Armor::ChainShirt => 50,
+            Armor::ScaleMail => 50,
+            Armor::Breastplate => 400,
+            Armor::HalfPlate => 750,
+            Armor::RingMail => 30,
+            Armor::ChainMail => 75,
+            Armor::Splint => 200,
+            Armor::Plate => 1500,
+        }
+    }
+}
+
+impl FromStr for Armor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        from_name(s).ok_or_else(|| anyhow::anyhow!("unknown armor '{}'", s))
+    }
+}
+
 impl From<Armor> for u8 {
     fn from(armor: Armor) -> u8 {
         match armor {
@@ -199,7 +530,8 @@ impl From<Armor> for u8 {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[allow(clippy::enum_variant_names)]
 pub(crate) enum Shield {
     Shield,
     NoShield,
@@ -214,34 +546,831 @@ impl From<Shield> for u8 {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-pub enum UnarmoredDefense {
-    Barbarian(u8),
-    Monk(u8),
-    None,
+/// A way of working out base AC. Only one applies at a time, so the best one
+/// the character qualifies for wins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter, Serialize, Deserialize)]
+pub enum AcFormula {
+    /// Whatever armor is worn, plus DEX up to its cap
+    Armor,
+    /// Barbarian Unarmored Defense
+    Barbarian,
+    /// Monk Unarmored Defense
+    Monk,
+    MageArmor,
+    /// The Draconic Bloodline sorcerer feature
+    DraconicResilience,
+    /// Tortle Natural Armor
+    Tortle,
+    /// Lizardfolk Natural Armor
+    Lizardfolk,
+    /// `base` plus an ability modifier, without DEX unless that's the ability
+    #[strum(disabled)]
+    Homebrew {
+        base: u8,
+        ability: Ability,
+    },
+}
+
+impl AcFormula {
+    /// Whether the formula can be used with this armor and shield.
+    pub fn allows(&self, armor: Armor, shield: Shield) -> bool {
+        match self {
+            AcFormula::Armor => true,
+            AcFormula::Monk => armor == Armor::NoArmor && matches!(shield, Shield::NoShield),
+            _ => armor == Armor::NoArmor,
+        }
+    }
+
+    /// The flat part of the formula.
+    pub fn base(&self, armor: Armor) -> u8 {
+        match self {
+            AcFormula::Armor => u8::from(armor),
+            AcFormula::Barbarian | AcFormula::Monk => 10,
+            AcFormula::MageArmor | AcFormula::DraconicResilience | AcFormula::Lizardfolk => 13,
+            AcFormula::Tortle => 17,
+            AcFormula::Homebrew { base, .. } => *base,
+        }
+    }
+
+    /// The most DEX the formula adds, `None` if it's uncapped and `Some(0)`
+    /// if DEX doesn't count at all.
+    pub fn max_dex(&self, armor: Armor) -> Option<i8> {
+        match self {
+            AcFormula::Armor => armor.max_dex(),
+            AcFormula::Tortle | AcFormula::Homebrew { .. } => Some(0),
+            _ => None,
+        }
+    }
+
+    /// The ability added on top of DEX.
+    pub fn ability(&self) -> Option<Ability> {
+        match self {
+            AcFormula::Barbarian => Some(Ability::Constitution),
+            AcFormula::Monk => Some(Ability::Wisdom),
+            AcFormula::Homebrew { ability, .. } => Some(*ability),
+            _ => None,
+        }
+    }
+
+    /// Lets the user pick the formulas they have besides wearing armor,
+    /// plus homebrew ones.
+    pub fn prompt_many() -> Vec<AcFormula> {
+        let choices = AcFormula::iter()
+            .filter(|formula| *formula != AcFormula::Armor)
+            .map(FormulaChoice::Known)
+            .chain(std::iter::once(FormulaChoice::Homebrew))
+            .collect();
+        let mut formulas = vec![];
+        for choice in multi_select("Does anything else set your AC?", choices) {
+            match choice {
+                FormulaChoice::Known(formula) => formulas.push(formula),
+                FormulaChoice::Homebrew => formulas.push(AcFormula::Homebrew {
+                    base: input_map("Base AC of the homebrew formula: ", str::parse::<u8>),
+                    ability: select("Which ability is added to it?", Ability::iter().collect()),
+                }),
+            }
+        }
+        formulas
+    }
+}
+
+impl std::fmt::Display for AcFormula {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AcFormula::Armor => write!(f, "Armor"),
+            AcFormula::Barbarian => write!(f, "Unarmored Defense (Barbarian)"),
+            AcFormula::Monk => write!(f, "Unarmored Defense (Monk)"),
+            AcFormula::MageArmor => write!(f, "Mage Armor"),
+            AcFormula::DraconicResilience => write!(f, "Draconic Resilience"),
+            AcFormula::Tortle => write!(f, "Tortle Natural Armor"),
+            AcFormula::Lizardfolk => write!(f, "Lizardfolk Natural Armor"),
+            AcFormula::Homebrew { base, ability } => {
+                write!(f, "Homebrew ({} + {})", base, ability.abbreviation())
+            }
+        }
+    }
+}
+
+/// Accepts the names of the formulas, with `barbarian`, `monk`, `tortle` and
+/// `lizardfolk` as short forms, and homebrew formulas like `12+con`.
+impl FromStr for AcFormula {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((base, ability)) = s.split_once('+') {
+            let ability = Ability::iter().find(|known| {
+                known.abbreviation().eq_ignore_ascii_case(ability.trim())
+                    || known.to_string().eq_ignore_ascii_case(ability.trim())
+            });
+            if let (Ok(base), Some(ability)) = (base.trim().parse::<u8>(), ability) {
+                return Ok(AcFormula::Homebrew { base, ability });
+            }
+        }
+        let short = match s.to_lowercase().as_str() {
+            "barbarian" => Some(AcFormula::Barbarian),
+            "monk" => Some(AcFormula::Monk),
+            "tortle" => Some(AcFormula::Tortle),
+            "lizardfolk" => Some(AcFormula::Lizardfolk),
+            _ => None,
+        };
+        short
+            .or_else(|| from_name(s))
+            .ok_or_else(|| anyhow::anyhow!("unknown AC formula '{}'", s))
+    }
+}
+
+#[derive(Debug, Clone)]
+enum FormulaChoice {
+    Known(AcFormula),
+    Homebrew,
+}
+
+impl std::fmt::Display for FormulaChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FormulaChoice::Known(formula) => write!(f, "{}", formula),
+            FormulaChoice::Homebrew => write!(f, "Homebrew"),
+        }
+    }
+}
+
+/// Magic items and features that add to AC on top of armor, DEX and a shield.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter, Serialize, Deserialize)]
+pub enum AcBonus {
+    /// +1, +2 or +3 armor
+    MagicArmor(u8),
+    /// A +1, +2 or +3 shield
+    MagicShield(u8),
+    RingOfProtection,
+    CloakOfProtection,
+    BracersOfDefense,
+    /// The Defense fighting style
+    Defense,
+    /// Warforged Integrated Protection
+    IntegratedProtection,
+    /// The Bladesinger's Bladesong, adding INT
+    Bladesong,
+}
+
+impl AcBonus {
+    pub fn bonus(&self, abilities: &AbilityScores) -> u8 {
+        match self {
+            AcBonus::MagicArmor(bonus) | AcBonus::MagicShield(bonus) => *bonus,
+            AcBonus::RingOfProtection
+            | AcBonus::CloakOfProtection
+            | AcBonus::Defense
+            | AcBonus::IntegratedProtection => 1,
+            AcBonus::BracersOfDefense => 2,
+            AcBonus::Bladesong => abilities.modifier(Ability::Intelligence).max(1) as u8,
+        }
+    }
+
+    /// Whether the bonus counts with this armor and shield.
+    pub fn applies(&self, armor: Armor, shield: Shield) -> bool {
+        let armored = armor != Armor::NoArmor;
+        let shielded = matches!(shield, Shield::Shield);
+        match self {
+            AcBonus::MagicArmor(_) | AcBonus::Defense => armored,
+            AcBonus::MagicShield(_) => shielded,
+            AcBonus::RingOfProtection
+            | AcBonus::CloakOfProtection
+            | AcBonus::IntegratedProtection => true,
+            AcBonus::BracersOfDefense => !armored && !shielded,
+            // light armor is fine, it's the only armor without a DEX cap
+            AcBonus::Bladesong => armor.max_dex().is_none() && !shielded,
+        }
+    }
+
+    /// Magic armor and shields, which need to know how magic they are.
+    fn is_enhancement(&self) -> bool {
+        matches!(self, AcBonus::MagicArmor(_) | AcBonus::MagicShield(_))
+    }
+
+    /// Asks about magic armor and shields, then any other bonuses.
+    pub fn prompt_many(armor: Armor, shield: Shield) -> Vec<AcBonus> {
+        let enhancement = |prompt: &str| {
+            let choices = vec!["No", "+1", "+2", "+3"];
+            let choice = select(prompt, choices.clone());
+            choices.iter().position(|c| *c == choice).unwrap_or(0) as u8
+        };
+        let mut bonuses = vec![];
+        if armor != Armor::NoArmor {
+            match enhancement("Is your armor magical?") {
+                0 => {}
+                bonus => bonuses.push(AcBonus::MagicArmor(bonus)),
+            }
+        }
+        if let Shield::Shield = shield {
+            match enhancement("Is your shield magical?") {
+                0 => {}
+                bonus => bonuses.push(AcBonus::MagicShield(bonus)),
+            }
+        }
+        let others = AcBonus::iter()
+            .filter(|bonus| !bonus.is_enhancement())
+            .collect();
+        bonuses.extend(multi_select("Anything else that adds to your AC?", others));
+        bonuses
+    }
+}
+
+impl std::fmt::Display for AcBonus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AcBonus::MagicArmor(bonus) => write!(f, "+{} armor", bonus),
+            AcBonus::MagicShield(bonus) => write!(f, "+{} shield", bonus),
+            AcBonus::RingOfProtection => write!(f, "Ring of Protection"),
+            AcBonus::CloakOfProtection => write!(f, "Cloak of Protection"),
+            AcBonus::BracersOfDefense => write!(f, "Bracers of Defense"),
+            AcBonus::Defense => write!(f, "Defense fighting style"),
+            AcBonus::IntegratedProtection => write!(f, "Integrated Protection"),
+            AcBonus::Bladesong => write!(f, "Bladesong"),
+        }
+    }
+}
+
+/// Accepts `armor+1` and `shield+2` alongside the names of the other bonuses,
+/// e.g. `ring-of-protection` or `defense`.
+impl FromStr for AcBonus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let magic = |s: &str, prefix: &str| {
+            s.to_lowercase()
+                .strip_prefix(prefix)
+                .and_then(|bonus| bonus.trim_start_matches(['+', ' ']).parse::<u8>().ok())
+                .filter(|bonus| (1..=3).contains(bonus))
+        };
+        if let Some(bonus) = magic(s, "armor") {
+            return Ok(AcBonus::MagicArmor(bonus));
+        }
+        if let Some(bonus) = magic(s, "shield") {
+            return Ok(AcBonus::MagicShield(bonus));
+        }
+        from_name::<AcBonus>(s)
+            .filter(|bonus| !bonus.is_enhancement())
+            .or_else(|| from_name::<AcBonus>(&format!("{} fighting style", s)))
+            .ok_or_else(|| anyhow::anyhow!("unknown AC bonus '{}'", s))
+    }
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct Ac(pub Armor, pub u8, pub Shield, pub UnarmoredDefense);
+pub(crate) struct Ac {
+    pub armor: Armor,
+    pub abilities: AbilityScores,
+    pub shield: Shield,
+    pub formulas: Vec<AcFormula>,
+    pub bonuses: Vec<AcBonus>,
+}
 
 impl Ac {
-    pub(crate) fn calculate(&self) -> u8 {
-        let Ac(armor, dex, shield, unarmored_defense) = *self;
-        let dex = match (armor, shield, unarmored_defense) {
-            (Armor::NoArmor, _, UnarmoredDefense::Barbarian(ability)) => dex + ability,
-            (Armor::NoArmor, Shield::NoShield, UnarmoredDefense::Monk(ability)) => dex + ability,
-            _ => dex,
+    /// The bonuses that count with the armor and shield being worn.
+    pub(crate) fn bonuses(&self) -> Vec<AcBonus> {
+        let Ac {
+            armor,
+            shield,
+            bonuses,
+            ..
+        } = self;
+        bonuses
+            .iter()
+            .copied()
+            .filter(|bonus| bonus.applies(*armor, *shield))
+            .collect()
+    }
+
+    /// What `formula` alone adds up to, before the shield and other bonuses.
+    fn formula_components(&self, formula: AcFormula) -> Vec<AcComponent> {
+        let Ac {
+            armor, abilities, ..
+        } = self;
+        let base = match formula {
+            AcFormula::Armor => AcSource::Armor(*armor),
+            formula => AcSource::Formula(formula),
         };
-        let dex = match armor {
-            Armor::NoArmor | Armor::Padded | Armor::Leather | Armor::StuddedLeather => dex,
-            Armor::Hide
-            | Armor::ChainShirt
-            | Armor::ScaleMail
-            | Armor::Breastplate
-            | Armor::HalfPlate => dex.clamp(0, 2),
-            Armor::RingMail | Armor::ChainMail | Armor::Splint | Armor::Plate => 0,
+        let mut components = vec![AcComponent::new(base, formula.base(*armor))];
+        let dex = abilities.modifier(Ability::Dexterity);
+        match formula.max_dex(*armor) {
+            // heavy armor ignores DEX entirely, penalties included; other
+            // formulas without DEX aren't worth mentioning it for
+            Some(0) if formula == AcFormula::Armor && dex != 0 => {
+                components.push(AcComponent::new(
+                    AcSource::Dex {
+                        modifier: dex,
+                        cap: Some(0),
+                    },
+                    0i8,
+                ))
+            }
+            Some(0) => {}
+            Some(max) if dex > max => components.push(AcComponent::new(
+                AcSource::Dex {
+                    modifier: dex,
+                    cap: Some(max),
+                },
+                max,
+            )),
+            _ => components.push(AcComponent::new(
+                AcSource::Dex {
+                    modifier: dex,
+                    cap: None,
+                },
+                dex,
+            )),
+        }
+        if let Some(ability) = formula.ability() {
+            components.push(AcComponent::new(
+                AcSource::Ability(ability),
+                abilities.modifier(ability),
+            ));
+        }
+        components
+    }
+
+    fn formula_ac(&self, formula: AcFormula) -> i16 {
+        self.formula_components(formula)
+            .iter()
+            .map(|component| component.ac)
+            .sum()
+    }
+
+    /// The formula giving the highest AC out of those that can be used;
+    /// wearing armor, or none, always can.
+    pub(crate) fn formula(&self) -> AcFormula {
+        let Ac {
+            armor,
+            shield,
+            formulas,
+            ..
+        } = self;
+        formulas
+            .iter()
+            .copied()
+            .filter(|formula| formula.allows(*armor, *shield))
+            .fold(AcFormula::Armor, |best, formula| {
+                match self.formula_ac(formula) > self.formula_ac(best) {
+                    true => formula,
+                    false => best,
+                }
+            })
+    }
+
+    /// Everything that adds up to the AC, in the order it's worked out.
+    pub(crate) fn breakdown(&self) -> AcBreakdown {
+        let Ac {
+            abilities, shield, ..
+        } = self;
+        let formula = self.formula();
+        let mut components = self.formula_components(formula);
+        if let Shield::Shield = shield {
+            components.push(AcComponent::new(AcSource::Shield, u8::from(*shield)));
+        }
+        components.extend(
+            self.bonuses()
+                .into_iter()
+                .map(|bonus| AcComponent::new(AcSource::Bonus(bonus), bonus.bonus(abilities))),
+        );
+        let total: i16 = components.iter().map(|component| component.ac).sum();
+        AcBreakdown {
+            formula,
+            components,
+            total: u8::try_from(total.max(0)).unwrap_or(u8::MAX),
+        }
+    }
+}
+
+/// Where an AC's points come from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AcBreakdown {
+    /// The formula that gave the best AC
+    pub formula: AcFormula,
+    pub components: Vec<AcComponent>,
+    pub total: u8,
+}
+
+/// Shown as a table, one line per component.
+impl std::fmt::Display for AcBreakdown {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let labels: Vec<_> = self
+            .components
+            .iter()
+            .map(|component| component.source.to_string())
+            .collect();
+        let width = labels.iter().map(String::len).max().unwrap_or(0);
+        for (i, (label, component)) in labels.iter().zip(&self.components).enumerate() {
+            let ac = match i {
+                0 => component.ac.to_string(),
+                _ => format!("{:+}", component.ac),
+            };
+            writeln!(f, "{:<width$} {:>3}", label, ac)?;
+        }
+        write!(f, "{:<width$} {:>3}", "Total", self.total)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AcComponent {
+    pub source: AcSource,
+    pub ac: i16,
+}
+
+impl AcComponent {
+    fn new(source: AcSource, ac: impl Into<i16>) -> Self {
+        Self {
+            source,
+            ac: ac.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum AcSource {
+    Armor(Armor),
+    /// The base of any formula other than wearing armor
+    Formula(AcFormula),
+    /// `cap` is set when it lowered the modifier
+    Dex {
+        modifier: i8,
+        cap: Option<i8>,
+    },
+    /// The ability a formula adds, like WIS for a Monk
+    Ability(Ability),
+    Shield,
+    Bonus(AcBonus),
+}
+
+impl std::fmt::Display for AcSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AcSource::Armor(armor) => write!(f, "{}", armor),
+            AcSource::Formula(formula) => write!(f, "{}", formula),
+            AcSource::Dex { cap: None, .. } => write!(f, "DEX"),
+            AcSource::Dex {
+                modifier,
+                cap: Some(0),
+            } => write!(f, "DEX {:+}, ignored by heavy armor", modifier),
+            AcSource::Dex {
+                modifier,
+                cap: Some(cap),
+            } => write!(f, "DEX {:+}, capped at {:+}", modifier, cap),
+            AcSource::Ability(ability) => write!(f, "{}", ability.abbreviation()),
+            AcSource::Shield => write!(f, "Shield"),
+            AcSource::Bonus(bonus) => write!(f, "{}", bonus),
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, EnumIter, Display, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize,
+)]
+pub enum DamageType {
+    Acid,
+    Bludgeoning,
+    Cold,
+    Fire,
+    Force,
+    Lightning,
+    Necrotic,
+    Piercing,
+    Poison,
+    Psychic,
+    Radiant,
+    Slashing,
+    Thunder,
+}
+
+#[derive(Debug)]
+pub struct DamageTypeParseError;
+
+impl std::fmt::Display for DamageTypeParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown damage type")
+    }
+}
+
+impl std::error::Error for DamageTypeParseError {}
+
+impl FromStr for DamageType {
+    type Err = DamageTypeParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        from_name(s).ok_or(DamageTypeParseError)
+    }
+}
+
+/// Damage types a character or creature takes less, more or no damage from.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Defenses {
+    #[serde(default)]
+    pub resistances: BTreeSet<DamageType>,
+    #[serde(default)]
+    pub vulnerabilities: BTreeSet<DamageType>,
+    #[serde(default)]
+    pub immunities: BTreeSet<DamageType>,
+}
+
+impl Defenses {
+    pub fn prompt() -> Defenses {
+        let ask = |prompt: &str| -> BTreeSet<DamageType> {
+            multi_select(prompt, DamageType::iter().collect())
+                .into_iter()
+                .collect()
+        };
+        Defenses {
+            resistances: ask("Resistances:"),
+            vulnerabilities: ask("Vulnerabilities:"),
+            immunities: ask("Immunities:"),
+        }
+    }
+
+    /// The damage actually taken from `amount` damage of `damage_type`.
+    ///
+    /// Immunity wins outright. Otherwise resistance halves, rounding down,
+    /// and vulnerability then doubles, so having both can still cost a point:
+    /// 25 becomes 12, then 24.
+    pub fn adjust(&self, amount: u32, damage_type: Option<DamageType>) -> u32 {
+        let Some(damage_type) = damage_type else {
+            return amount;
+        };
+        if self.immunities.contains(&damage_type) {
+            return 0;
+        }
+        let mut amount = amount;
+        if self.resistances.contains(&damage_type) {
+            amount /= 2;
+        }
+        if self.vulnerabilities.contains(&damage_type) {
+            amount = amount.saturating_mul(2);
+        }
+        amount
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.resistances.is_empty() && self.vulnerabilities.is_empty() && self.immunities.is_empty()
+    }
+}
+
+impl std::fmt::Display for Defenses {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let list = |types: &BTreeSet<DamageType>| {
+            types
+                .iter()
+                .map(DamageType::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
         };
+        let defenses = [
+            ("Resistant to", &self.resistances),
+            ("Vulnerable to", &self.vulnerabilities),
+            ("Immune to", &self.immunities),
+        ]
+        .into_iter()
+        .filter(|(_, types)| !types.is_empty())
+        .map(|(label, types)| format!("{} {}", label, list(types)))
+        .collect::<Vec<_>>();
+        write!(f, "{}", defenses.join("; "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn abilities(dex: i8, con: i8, wis: i8) -> AbilityScores {
+        AbilityScores {
+            dexterity: score_for(dex),
+            constitution: score_for(con),
+            wisdom: score_for(wis),
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_ability_modifiers() -> TResult {
+        test(|| {
+            let modifiers = [1, 3, 8, 9, 10, 11, 12, 15, 20, 30].map(modifier);
+            assert_eq!(modifiers, [-5, -4, -1, -1, 0, 0, 1, 2, 5, 10]);
+            assert_eq!(parse_score("14").unwrap(), 14);
+            assert_eq!(parse_score("+2").unwrap(), 14);
+            assert_eq!(parse_score("-1").unwrap(), 8);
+            assert!(parse_score("0").is_err());
+            assert!(parse_score("31").is_err());
+            assert!(parse_score("+11").is_err());
+            modifiers
+        })
+    }
+
+    #[test_case]
+    fn test_ac_negative_dex() -> TResult {
+        test(|| {
+            let light = Ac {
+                armor: Armor::Leather,
+                abilities: abilities(-2, 0, 0),
+                shield: Shield::NoShield,
+                formulas: vec![],
+                bonuses: vec![],
+            };
+            assert_eq!(light.breakdown().total, 9);
+            let medium = Ac {
+                armor: Armor::Breastplate,
+                abilities: abilities(-1, 0, 0),
+                shield: Shield::Shield,
+                formulas: vec![],
+                bonuses: vec![],
+            };
+            assert_eq!(medium.breakdown().total, 15);
+            let heavy = Ac {
+                armor: Armor::Plate,
+                abilities: abilities(-3, 0, 0),
+                shield: Shield::NoShield,
+                formulas: vec![],
+                bonuses: vec![],
+            };
+            assert_eq!(heavy.breakdown().total, 18);
+            light.breakdown().total
+        })
+    }
+
+    #[test_case]
+    fn test_ac_unarmored_defense_negative() -> TResult {
+        test(|| {
+            let barbarian = Ac {
+                armor: Armor::NoArmor,
+                abilities: abilities(-1, -1, 0),
+                shield: Shield::Shield,
+                formulas: vec![AcFormula::Barbarian],
+                bonuses: vec![],
+            };
+            // a negative CON makes Unarmored Defense worse than no armor at all,
+            // so the plain 10 + DEX is used instead
+            assert_eq!(barbarian.formula(), AcFormula::Armor);
+            assert_eq!(barbarian.breakdown().total, 11);
+            let monk = Ac {
+                armor: Armor::NoArmor,
+                abilities: abilities(3, 0, -1),
+                shield: Shield::NoShield,
+                formulas: vec![AcFormula::Monk],
+                bonuses: vec![],
+            };
+            assert_eq!(monk.formula(), AcFormula::Armor);
+            assert_eq!(monk.breakdown().total, 13);
+            monk.breakdown().total
+        })
+    }
+
+    #[test_case]
+    fn test_ac_bonuses() -> TResult {
+        test(|| {
+            let bonuses: Vec<AcBonus> = [
+                "armor+1",
+                "shield+2",
+                "ring-of-protection",
+                "bracers-of-defense",
+                "defense",
+            ]
+            .iter()
+            .map(|bonus| bonus.parse().unwrap())
+            .collect();
+            let fighter = Ac {
+                armor: Armor::Plate,
+                abilities: abilities(0, 0, 0),
+                shield: Shield::Shield,
+                formulas: vec![],
+                bonuses: bonuses.clone(),
+            };
+            // bracers don't work with armor or a shield
+            assert_eq!(fighter.breakdown().total, 18 + 2 + 1 + 2 + 1 + 1);
+            let monk = Ac {
+                armor: Armor::NoArmor,
+                abilities: abilities(3, 0, 3),
+                shield: Shield::NoShield,
+                formulas: vec![AcFormula::Monk],
+                bonuses,
+            };
+            // neither do the armor, shield and fighting style bonuses without them
+            assert_eq!(monk.breakdown().total, 10 + 3 + 3 + 1 + 2);
+            fighter.breakdown().total
+        })
+    }
+
+    #[test_case]
+    fn test_ac_best_formula() -> TResult {
+        test(|| {
+            let formulas: Vec<AcFormula> = ["mage-armor", "tortle", "12+con"]
+                .iter()
+                .map(|formula| formula.parse().unwrap())
+                .collect();
+            let ac = |dex, con| Ac {
+                armor: Armor::NoArmor,
+                abilities: abilities(dex, con, 0),
+                shield: Shield::Shield,
+                formulas: formulas.clone(),
+                bonuses: vec![AcBonus::Bladesong],
+            };
+            // Bladesong adds at least +1, but not with a shield
+            assert_eq!(
+                (ac(2, 0).formula(), ac(2, 0).breakdown().total),
+                (AcFormula::Tortle, 19)
+            );
+            assert_eq!(
+                (ac(5, 0).formula(), ac(5, 0).breakdown().total),
+                (AcFormula::MageArmor, 20)
+            );
+            let homebrew = ac(2, 6);
+            assert_eq!(homebrew.breakdown().total, 20);
+            // armor rules the others out
+            let armored = Ac {
+                armor: Armor::Leather,
+                abilities: abilities(2, 6, 0),
+                shield: Shield::NoShield,
+                formulas: formulas.clone(),
+                bonuses: vec![AcBonus::Bladesong],
+            };
+            assert_eq!(
+                (armored.formula(), armored.breakdown().total),
+                (AcFormula::Armor, 14)
+            );
+            homebrew.formula()
+        })
+    }
+
+    #[test_case]
+    fn test_ac_breakdown() -> TResult {
+        test(|| {
+            let ac = Ac {
+                armor: Armor::HalfPlate,
+                abilities: abilities(4, 0, 0),
+                shield: Shield::Shield,
+                formulas: vec![],
+                bonuses: vec![AcBonus::MagicShield(1)],
+            };
+            let breakdown = ac.breakdown();
+            assert_eq!(
+                breakdown.to_string(),
+                [
+                    "Half plate            15",
+                    "DEX +4, capped at +2  +2",
+                    "Shield                +2",
+                    "+1 shield             +1",
+                    "Total                 20",
+                ]
+                .join("\n")
+            );
+            breakdown
+        })
+    }
+
+    #[test_case]
+    fn test_armor_requirements() -> TResult {
+        test(|| {
+            let slowed = [
+                Armor::ChainMail.speed_penalty(12),
+                Armor::ChainMail.speed_penalty(13),
+                Armor::Plate.speed_penalty(14),
+                Armor::RingMail.speed_penalty(3),
+            ];
+            assert_eq!(slowed, [true, false, true, false]);
+            let stealthy: Vec<_> = Armor::iter()
+                .filter(|armor| !armor.stealth_disadvantage())
+                .collect();
+            assert_eq!(
+                stealthy,
+                [
+                    Armor::NoArmor,
+                    Armor::Leather,
+                    Armor::StuddedLeather,
+                    Armor::Hide,
+                    Armor::ChainShirt,
+                    Armor::Breastplate,
+                ]
+            );
+            slowed
+        })
+    }
 
-        u8::from(armor) + dex + u8::from(shield)
+    #[test_case]
+    fn test_damage_defenses() -> TResult {
+        test(|| {
+            let defenses = Defenses {
+                resistances: BTreeSet::from([DamageType::Fire, DamageType::Cold]),
+                vulnerabilities: BTreeSet::from([DamageType::Fire, DamageType::Radiant]),
+                immunities: BTreeSet::from([DamageType::Poison, DamageType::Radiant]),
+            };
+            let taken = [
+                defenses.adjust(25, None),
+                // resistance rounds down
+                defenses.adjust(7, Some(DamageType::Cold)),
+                // halved then doubled loses the odd point
+                defenses.adjust(25, Some(DamageType::Fire)),
+                defenses.adjust(24, Some(DamageType::Fire)),
+                // immunity beats vulnerability
+                defenses.adjust(10, Some(DamageType::Radiant)),
+                defenses.adjust(10, Some(DamageType::Poison)),
+                defenses.adjust(10, Some(DamageType::Slashing)),
+            ];
+            assert_eq!(taken, [25, 3, 24, 24, 0, 0, 10]);
+            taken
+        })
     }
 }
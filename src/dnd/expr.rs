@@ -0,0 +1,648 @@
+use std::{fmt::Display, str::FromStr};
+
+use nom::{
+    branch::alt,
+    bytes::complete::tag,
+    character::complete::{char, digit1, multispace0, one_of},
+    combinator::{all_consuming, map, map_res, opt},
+    multi::many0,
+    sequence::{delimited, pair, preceded, tuple},
+    IResult,
+};
+use rand::Rng;
+use serde::Serialize;
+
+use super::Dice;
+
+/// Upper bound on the number of dice in a single pool, so a typo like
+/// `99999999d6` doesn't hang the roller.
+pub const MAX_DICE: u32 = 1000;
+
+/// Which dice of a pool count towards its total. Drop modifiers (`dl1`) are
+/// stored as the equivalent keep (`kh3` on `4d6`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keep {
+    Highest(u32),
+    Lowest(u32),
+}
+
+/// Upper bound on how many extra dice a single exploding die can add.
+pub const MAX_EXPLOSIONS: u32 = 100;
+
+/// A group of identical dice rolled together, e.g. the `4d6kh3` in `4d6kh3 + 2`.
+///
+/// Modifiers are applied per die in a fixed order: reroll once (`r<=2`),
+/// minimum (`min2`), explode (`!`), and finally keep/drop over the whole pool.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DicePool {
+    pub count: u32,
+    pub dice: Dice,
+    pub keep: Option<Keep>,
+    pub reroll: Option<u8>,
+    pub minimum: Option<u8>,
+    pub explode: bool,
+}
+
+impl DicePool {
+    pub fn new(count: u32, dice: Dice) -> Self {
+        Self {
+            count,
+            dice,
+            keep: None,
+            reroll: None,
+            minimum: None,
+            explode: false,
+        }
+    }
+
+    pub fn keep(self, keep: Keep) -> Self {
+        Self {
+            keep: Some(keep),
+            ..self
+        }
+    }
+
+    pub fn reroll(self, at_most: u8) -> Self {
+        Self {
+            reroll: Some(at_most),
+            ..self
+        }
+    }
+
+    pub fn minimum(self, minimum: u8) -> Self {
+        Self {
+            minimum: Some(minimum),
+            ..self
+        }
+    }
+
+    pub fn explode(self) -> Self {
+        Self {
+            explode: true,
+            ..self
+        }
+    }
+
+    fn roll_die(&self, rng: &mut impl Rng, from_explosion: bool) -> DieRoll {
+        let mut die = DieRoll {
+            dice: self.dice,
+            value: self.dice.roll(rng),
+            log: vec![],
+            from_explosion,
+            dropped: false,
+        };
+        tracing::trace!("Rolled a {} on a d{}", die.value, u8::from(self.dice));
+
+        if let Some(at_most) = self.reroll {
+            if die.value <= at_most {
+                let from = die.value;
+                die.value = self.dice.roll(rng);
+                die.log.push(RollEvent::Rerolled { from });
+                tracing::trace!("Rerolled a {} into a {}", from, die.value);
+            }
+        }
+        if let Some(minimum) = self.minimum {
+            if die.value < minimum {
+                die.log.push(RollEvent::Raised { from: die.value });
+                tracing::trace!("Raised a {} to {}", die.value, minimum);
+                die.value = minimum;
+            }
+        }
+        die
+    }
+
+    /// Rolls every die in the pool, adding exploded dice and marking the ones
+    /// the keep modifier drops.
+    pub fn roll(&self, rng: &mut impl Rng) -> Vec<DieRoll> {
+        let max = u8::from(self.dice);
+        let mut rolls = vec![];
+        for _ in 0..self.count {
+            let mut die = self.roll_die(rng, false);
+            let mut explosions = 0;
+            while self.explode && max > 1 && die.value == max && explosions < MAX_EXPLOSIONS {
+                die.log.push(RollEvent::Exploded);
+                tracing::trace!("A d{} exploded", max);
+                rolls.push(die);
+                die = self.roll_die(rng, true);
+                explosions += 1;
+            }
+            rolls.push(die);
+        }
+
+        if let Some(keep) = self.keep {
+            let mut order = (0..rolls.len()).collect::<Vec<_>>();
+            order.sort_by_key(|&i| rolls[i].value);
+            let dropped = match keep {
+                Keep::Highest(n) => &order[..order.len().saturating_sub(n as usize)],
+                Keep::Lowest(n) => &order[(n as usize).min(order.len())..],
+            };
+            for &i in dropped {
+                rolls[i].dropped = true;
+            }
+        }
+        rolls
+    }
+}
+
+impl Display for DicePool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}d{}", self.count, u8::from(self.dice))?;
+        if let Some(at_most) = self.reroll {
+            write!(f, "r<={}", at_most)?;
+        }
+        if let Some(minimum) = self.minimum {
+            write!(f, "min{}", minimum)?;
+        }
+        if self.explode {
+            write!(f, "!")?;
+        }
+        match self.keep {
+            Some(Keep::Highest(n)) => write!(f, "kh{}", n),
+            Some(Keep::Lowest(n)) => write!(f, "kl{}", n),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A full dice expression such as `2d6+3`, `1d20 + 5` or `(1d4 + 1) * 2`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiceExpr {
+    Roll(DicePool),
+    Flat(i32),
+    Neg(Box<DiceExpr>),
+    Add(Box<DiceExpr>, Box<DiceExpr>),
+    Sub(Box<DiceExpr>, Box<DiceExpr>),
+    Mul(Box<DiceExpr>, Box<DiceExpr>),
+}
+
+/// Something that happened to a die after it was first rolled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum RollEvent {
+    /// The die showed `from` and was rerolled.
+    Rerolled { from: u8 },
+    /// The die showed `from`, below the pool's minimum, and was raised to it.
+    Raised { from: u8 },
+    /// The die rolled its maximum and added another die to the pool.
+    Exploded,
+}
+
+/// A single die that was rolled while evaluating an expression.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DieRoll {
+    pub dice: Dice,
+    pub value: u8,
+    pub log: Vec<RollEvent>,
+    /// Set when this die was added by an exploding die rather than rolled directly.
+    pub from_explosion: bool,
+    /// Set when a keep/drop modifier discarded this die from the total.
+    pub dropped: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RollResult {
+    pub total: i32,
+    pub dice: Vec<DieRoll>,
+}
+
+impl DiceExpr {
+    pub fn roll(&self, rng: &mut impl Rng) -> RollResult {
+        let mut dice = vec![];
+        let total = self.eval(rng, &mut dice);
+        RollResult { total, dice }
+    }
+
+    /// Arithmetic saturates, so `2147483647 + 1` is as high as a total goes
+    /// rather than a panic.
+    fn eval(&self, rng: &mut impl Rng, dice: &mut Vec<DieRoll>) -> i32 {
+        match self {
+            DiceExpr::Roll(pool) => {
+                let rolls = pool.roll(rng);
+                let total = rolls
+                    .iter()
+                    .filter(|die| !die.dropped)
+                    .map(|die| i32::from(die.value))
+                    .sum();
+                dice.extend(rolls);
+                total
+            }
+            DiceExpr::Flat(value) => *value,
+            DiceExpr::Neg(expr) => expr.eval(rng, dice).saturating_neg(),
+            DiceExpr::Add(lhs, rhs) => lhs.eval(rng, dice).saturating_add(rhs.eval(rng, dice)),
+            DiceExpr::Sub(lhs, rhs) => lhs.eval(rng, dice).saturating_sub(rhs.eval(rng, dice)),
+            DiceExpr::Mul(lhs, rhs) => lhs.eval(rng, dice).saturating_mul(rhs.eval(rng, dice)),
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            DiceExpr::Add(..) | DiceExpr::Sub(..) => 0,
+            DiceExpr::Mul(..) => 1,
+            DiceExpr::Neg(..) => 2,
+            DiceExpr::Roll(..) | DiceExpr::Flat(..) => 3,
+        }
+    }
+
+    fn fmt_operand(&self, f: &mut std::fmt::Formatter<'_>, min: u8) -> std::fmt::Result {
+        if self.precedence() < min {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+impl Display for DiceExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiceExpr::Roll(pool) => write!(f, "{}", pool),
+            DiceExpr::Flat(value) => write!(f, "{}", value),
+            DiceExpr::Neg(expr) => {
+                write!(f, "-")?;
+                expr.fmt_operand(f, 2)
+            }
+            DiceExpr::Add(lhs, rhs) => {
+                lhs.fmt_operand(f, 0)?;
+                write!(f, " + ")?;
+                rhs.fmt_operand(f, 1)
+            }
+            DiceExpr::Sub(lhs, rhs) => {
+                lhs.fmt_operand(f, 0)?;
+                write!(f, " - ")?;
+                rhs.fmt_operand(f, 1)
+            }
+            DiceExpr::Mul(lhs, rhs) => {
+                lhs.fmt_operand(f, 1)?;
+                write!(f, " * ")?;
+                rhs.fmt_operand(f, 2)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DiceExprParseError;
+
+impl std::fmt::Display for DiceExprParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid dice expression")
+    }
+}
+
+impl std::error::Error for DiceExprParseError {}
+
+fn ws<'a, O>(
+    inner: impl FnMut(&'a str) -> IResult<&'a str, O>,
+) -> impl FnMut(&'a str) -> IResult<&'a str, O> {
+    delimited(multispace0, inner, multispace0)
+}
+
+fn number(s: &str) -> IResult<&str, u32> {
+    map_res(digit1, str::parse::<u32>)(s)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum KeepTag {
+    KeepHighest,
+    KeepLowest,
+    DropHighest,
+    DropLowest,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Modifier {
+    Keep(KeepTag, u32),
+    Reroll(u8),
+    Minimum(u8),
+    Explode,
+}
+
+fn small_number(s: &str) -> IResult<&str, u8> {
+    map_res(digit1, str::parse::<u8>)(s)
+}
+
+fn modifier(s: &str) -> IResult<&str, Modifier> {
+    alt((
+        map(
+            pair(
+                alt((
+                    map(tag("kh"), |_| KeepTag::KeepHighest),
+                    map(tag("kl"), |_| KeepTag::KeepLowest),
+                    map(tag("dh"), |_| KeepTag::DropHighest),
+                    map(tag("dl"), |_| KeepTag::DropLowest),
+                    map(tag("k"), |_| KeepTag::KeepHighest),
+                )),
+                number,
+            ),
+            |(tag, n)| Modifier::Keep(tag, n),
+        ),
+        map(preceded(tag("r<="), small_number), Modifier::Reroll),
+        map_res(preceded(tag("r<"), small_number), |n| {
+            n.checked_sub(1)
+                .map(Modifier::Reroll)
+                .ok_or(DiceExprParseError)
+        }),
+        map(preceded(tag("min"), small_number), Modifier::Minimum),
+        map(char('!'), |_| Modifier::Explode),
+    ))(s)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Advantage {
+    Advantage,
+    Disadvantage,
+}
+
+fn advantage(s: &str) -> IResult<&str, Advantage> {
+    preceded(
+        multispace0,
+        alt((
+            map(tag("adv"), |_| Advantage::Advantage),
+            map(tag("dis"), |_| Advantage::Disadvantage),
+        )),
+    )(s)
+}
+
+fn apply_modifier(pool: DicePool, modifier: Modifier) -> Result<DicePool, DiceExprParseError> {
+    let faces = u8::from(pool.dice);
+    match modifier {
+        Modifier::Keep(..) if pool.keep.is_some() => Err(DiceExprParseError),
+        Modifier::Keep(_, n) if n == 0 || n > pool.count => Err(DiceExprParseError),
+        Modifier::Keep(tag, n) => {
+            let count = pool.count;
+            Ok(pool.keep(match tag {
+                KeepTag::KeepHighest => Keep::Highest(n),
+                KeepTag::KeepLowest => Keep::Lowest(n),
+                KeepTag::DropHighest => Keep::Lowest(count - n),
+                KeepTag::DropLowest => Keep::Highest(count - n),
+            }))
+        }
+        Modifier::Reroll(at_most) if pool.reroll.is_none() && (1..faces).contains(&at_most) => {
+            Ok(pool.reroll(at_most))
+        }
+        // a minimum of the highest face would make every die explode
+        Modifier::Minimum(minimum) if pool.explode && minimum == faces => Err(DiceExprParseError),
+        Modifier::Explode if pool.minimum == Some(faces) => Err(DiceExprParseError),
+        Modifier::Minimum(minimum) if pool.minimum.is_none() && (1..=faces).contains(&minimum) => {
+            Ok(pool.minimum(minimum))
+        }
+        Modifier::Explode if !pool.explode && faces > 1 => Ok(pool.explode()),
+        _ => Err(DiceExprParseError),
+    }
+}
+
+fn pool(s: &str) -> IResult<&str, DicePool> {
+    map_res(
+        tuple((
+            opt(number),
+            one_of("dD"),
+            alt((map(char('%'), |_| 100), number)),
+            many0(modifier),
+            opt(advantage),
+        )),
+        |(count, _d_tag, faces, modifiers, advantage)| {
+            let count = count.unwrap_or(1);
+            let faces = u8::try_from(faces).map_err(|_| DiceExprParseError)?;
+            if !(1..=MAX_DICE).contains(&count) || faces == 0 {
+                return Err(DiceExprParseError);
+            }
+            let pool = modifiers
+                .into_iter()
+                .try_fold(DicePool::new(count, Dice::from(faces)), apply_modifier)?;
+            match advantage {
+                None => Ok(pool),
+                // `d20 adv` is shorthand for `2d20kh1`, `d20 dis` for `2d20kl1`
+                Some(advantage) if count == 1 && pool.keep.is_none() => {
+                    let pool = DicePool { count: 2, ..pool };
+                    Ok(match advantage {
+                        Advantage::Advantage => pool.keep(Keep::Highest(1)),
+                        Advantage::Disadvantage => pool.keep(Keep::Lowest(1)),
+                    })
+                }
+                Some(_) => Err(DiceExprParseError),
+            }
+        },
+    )(s)
+}
+
+fn factor(s: &str) -> IResult<&str, DiceExpr> {
+    ws(alt((
+        map(preceded(char('-'), factor), |expr| {
+            DiceExpr::Neg(Box::new(expr))
+        }),
+        delimited(char('('), expr, char(')')),
+        map(pool, DiceExpr::Roll),
+        map_res(digit1, |digits: &str| digits.parse().map(DiceExpr::Flat)),
+    )))(s)
+}
+
+fn term(s: &str) -> IResult<&str, DiceExpr> {
+    let (s, (first, rest)) = pair(factor, many0(preceded(char('*'), factor)))(s)?;
+    let term = rest.into_iter().fold(first, |lhs, rhs| {
+        DiceExpr::Mul(Box::new(lhs), Box::new(rhs))
+    });
+    Ok((s, term))
+}
+
+fn expr(s: &str) -> IResult<&str, DiceExpr> {
+    let (s, (first, rest)) = pair(term, many0(pair(one_of("+-"), term)))(s)?;
+    let expr = rest.into_iter().fold(first, |lhs, (op, rhs)| match op {
+        '+' => DiceExpr::Add(Box::new(lhs), Box::new(rhs)),
+        _ => DiceExpr::Sub(Box::new(lhs), Box::new(rhs)),
+    });
+    Ok((s, expr))
+}
+
+impl FromStr for DiceExpr {
+    type Err = DiceExprParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (_, expr) = all_consuming(expr)(s).map_err(|_| DiceExprParseError)?;
+        Ok(expr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+    use pretty_assertions::assert_eq;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn roll(count: u32, dice: Dice) -> Box<DiceExpr> {
+        Box::new(DiceExpr::Roll(DicePool::new(count, dice)))
+    }
+
+    #[test_case]
+    fn test_parse_dice_expr_sum() -> TResult {
+        test(|| -> anyhow::Result<DiceExpr> {
+            let expr = DiceExpr::from_str("2d6+3")?;
+            assert_eq!(
+                expr,
+                DiceExpr::Add(roll(2, Dice::D6), Box::new(DiceExpr::Flat(3)))
+            );
+            let expr = DiceExpr::from_str("8d6 - 2")?;
+            assert_eq!(
+                expr,
+                DiceExpr::Sub(roll(8, Dice::D6), Box::new(DiceExpr::Flat(2)))
+            );
+            Ok(expr)
+        })
+    }
+
+    #[test_case]
+    fn test_parse_dice_expr_parentheses() -> TResult {
+        test(|| -> anyhow::Result<DiceExpr> {
+            let expr = DiceExpr::from_str("(d4 + 1) * 2")?;
+            assert_eq!(
+                expr,
+                DiceExpr::Mul(
+                    Box::new(DiceExpr::Add(
+                        roll(1, Dice::D4),
+                        Box::new(DiceExpr::Flat(1))
+                    )),
+                    Box::new(DiceExpr::Flat(2)),
+                )
+            );
+            assert_eq!(expr.to_string(), "(1d4 + 1) * 2");
+            assert_eq!(DiceExpr::from_str("1d%")?, *roll(1, Dice::Other(100)));
+            Ok(expr)
+        })
+    }
+
+    #[test_case]
+    fn test_parse_dice_expr_invalid() -> TResult {
+        test(|| {
+            for input in ["", "2d", "d0", "2d6 +", "(1d4", "1d6 3", "0d6", "1d256"] {
+                assert!(DiceExpr::from_str(input).is_err(), "{:?} parsed", input);
+            }
+        })
+    }
+
+    #[test_case]
+    fn test_parse_keep_and_advantage() -> TResult {
+        test(|| -> anyhow::Result<DiceExpr> {
+            let keep_highest =
+                |count, dice, n| DiceExpr::Roll(DicePool::new(count, dice).keep(Keep::Highest(n)));
+            assert_eq!(DiceExpr::from_str("4d6kh3")?, keep_highest(4, Dice::D6, 3));
+            assert_eq!(DiceExpr::from_str("4d6dl1")?, keep_highest(4, Dice::D6, 3));
+            assert_eq!(
+                DiceExpr::from_str("d20 adv")?,
+                keep_highest(2, Dice::D20, 1)
+            );
+            assert_eq!(
+                DiceExpr::from_str("2d20kl1")?,
+                DiceExpr::from_str("1d20dis")?
+            );
+            for input in ["4d6kh5", "4d6kh0", "2d20 adv", "d20kh1 adv"] {
+                assert!(DiceExpr::from_str(input).is_err(), "{:?} parsed", input);
+            }
+            let expr = DiceExpr::from_str("d20 adv + 5")?;
+            assert_eq!(expr.to_string(), "2d20kh1 + 5");
+            Ok(expr)
+        })
+    }
+
+    #[test_case]
+    fn test_roll_keep_marks_dropped() -> TResult {
+        test(|| -> anyhow::Result<RollResult> {
+            let mut rng = StdRng::seed_from_u64(7);
+            let expr = DiceExpr::from_str("4d6kh3")?;
+            for _ in 0..100 {
+                let result = expr.roll(&mut rng);
+                let dropped = result
+                    .dice
+                    .iter()
+                    .filter(|die| die.dropped)
+                    .collect::<Vec<_>>();
+                assert_eq!(dropped.len(), 1);
+                let lowest = result.dice.iter().map(|die| die.value).min().unwrap();
+                assert_eq!(dropped[0].value, lowest);
+                let sum: i32 = result.dice.iter().map(|die| i32::from(die.value)).sum();
+                assert_eq!(result.total, sum - i32::from(lowest));
+            }
+            Ok(expr.roll(&mut rng))
+        })
+    }
+
+    #[test_case]
+    fn test_parse_reroll_minimum_explode() -> TResult {
+        test(|| -> anyhow::Result<DiceExpr> {
+            let expr = DiceExpr::from_str("2d6r<=2")?;
+            assert_eq!(expr, DiceExpr::Roll(DicePool::new(2, Dice::D6).reroll(2)));
+            assert_eq!(DiceExpr::from_str("2d6r<3")?, expr);
+            assert_eq!(
+                DiceExpr::from_str("8d6min2")?,
+                DiceExpr::Roll(DicePool::new(8, Dice::D6).minimum(2))
+            );
+            let expr = DiceExpr::from_str("4d6!kh3 + 1")?;
+            assert_eq!(expr.to_string(), "4d6!kh3 + 1");
+            for input in [
+                "d6r<=6",
+                "d6r<1",
+                "d6min7",
+                "d1!",
+                "d6!!",
+                "d6min2min3",
+                "d6min6!",
+                "d6!min6",
+            ] {
+                assert!(DiceExpr::from_str(input).is_err(), "{:?} parsed", input);
+            }
+            Ok(expr)
+        })
+    }
+
+    #[test_case]
+    fn test_roll_reroll_minimum_explode_log() -> TResult {
+        test(|| {
+            let mut rng = StdRng::seed_from_u64(3);
+            let reroll = DicePool::new(10, Dice::D6).reroll(2);
+            let minimum = DicePool::new(10, Dice::D6).minimum(2);
+            let explode = DicePool::new(10, Dice::D6).explode();
+            for _ in 0..100 {
+                for die in reroll.roll(&mut rng) {
+                    match die.log.as_slice() {
+                        [] => assert!(die.value > 2),
+                        [RollEvent::Rerolled { from }] => assert!(*from <= 2),
+                        log => panic!("unexpected log {:?}", log),
+                    }
+                }
+                for die in minimum.roll(&mut rng) {
+                    assert!(die.value >= 2);
+                    if let [RollEvent::Raised { from }] = die.log.as_slice() {
+                        assert_eq!((*from, die.value), (1, 2));
+                    }
+                }
+                let rolls = explode.roll(&mut rng);
+                let exploded = rolls.iter().filter(|die| die.value == 6).count();
+                assert_eq!(rolls.len(), 10 + exploded);
+                assert_eq!(
+                    rolls.iter().filter(|die| die.from_explosion).count(),
+                    exploded
+                );
+                for die in rolls.iter().filter(|die| die.value == 6) {
+                    assert_eq!(die.log, vec![RollEvent::Exploded]);
+                }
+            }
+        })
+    }
+
+    #[test_case]
+    fn test_roll_dice_expr() -> TResult {
+        test(|| -> anyhow::Result<RollResult> {
+            let mut rng = StdRng::seed_from_u64(42);
+            let expr = DiceExpr::from_str("3d6 + 1d20 - 2")?;
+            for _ in 0..100 {
+                let result = expr.roll(&mut rng);
+                assert_eq!(result.dice.len(), 4);
+                assert!((2..=36).contains(&result.total));
+                let sum: i32 = result.dice.iter().map(|die| i32::from(die.value)).sum();
+                assert_eq!(result.total, sum - 2);
+            }
+            // totals saturate instead of overflowing
+            let huge = DiceExpr::from_str("2147483647 + 1 - -5 * 1000d6")?;
+            assert_eq!(huge.roll(&mut rng).total, i32::MAX);
+            Ok(expr.roll(&mut rng))
+        })
+    }
+}
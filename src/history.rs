@@ -0,0 +1,308 @@
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumIter, IntoEnumIterator};
+
+use crate::ctx::Ctx;
+use crate::dnd::{DiceExpr, RollResult};
+
+use crate::prelude::*;
+
+/// How many rolls "Browse" shows at once.
+const RECENT_ROLLS: usize = 20;
+
+#[derive(Debug, Default, clap::Args)]
+pub struct HistoryArgs {
+    #[command(subcommand)]
+    action: Option<HistoryAction>,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum HistoryAction {
+    /// Show the most recent rolls
+    Browse,
+    /// Show rolls whose label or expression contains some text
+    Filter { query: String },
+    /// Write the whole history to a file
+    Export {
+        path: String,
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+    },
+}
+
+pub fn roll_history(ctx: &mut Ctx, args: HistoryArgs) -> anyhow::Result<()> {
+    let records = ctx.history.load()?;
+    if records.is_empty() {
+        return ctx.output(&records, |_| tracing::info!("No rolls recorded yet"));
+    }
+
+    let action = args.action.unwrap_or_else(|| {
+        match select("What would you like to do?", Action::iter().collect()) {
+            Action::Browse => HistoryAction::Browse,
+            Action::Filter => HistoryAction::Filter {
+                query: input("Show rolls whose label or expression contains: "),
+            },
+            Action::Export => HistoryAction::Export {
+                format: select("Export as:", ExportFormat::iter().collect()),
+                path: input("Export to file: "),
+            },
+        }
+    });
+    match action {
+        HistoryAction::Browse => {
+            let skip = records.len().saturating_sub(RECENT_ROLLS);
+            ctx.output(&records[skip..], |records| {
+                for record in records {
+                    println!("{}", record);
+                }
+            })?;
+        }
+        HistoryAction::Filter { query } => {
+            let matching = filter(&records, &query);
+            ctx.output(&matching, |matching| {
+                tracing::info!("{} matching rolls", matching.len());
+                for record in matching {
+                    println!("{}", record);
+                }
+            })?;
+        }
+        HistoryAction::Export { path, format } => {
+            let contents = match format {
+                ExportFormat::Csv => to_csv(&records),
+                ExportFormat::Json => serde_json::to_string_pretty(&records)?,
+            };
+            std::fs::write(&path, contents)?;
+            tracing::info!("Exported {} rolls to {}", records.len(), path);
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Display, EnumIter, Clone)]
+enum Action {
+    #[strum(serialize = "Browse recent rolls")]
+    Browse,
+    #[strum(serialize = "Filter rolls")]
+    Filter,
+    #[strum(serialize = "Export history")]
+    Export,
+}
+
+#[derive(Debug, Display, EnumIter, Clone, clap::ValueEnum)]
+enum ExportFormat {
+    #[strum(serialize = "CSV")]
+    Csv,
+    #[strum(serialize = "JSON")]
+    Json,
+}
+
+/// A single roll as stored in the history file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RollRecord {
+    pub timestamp: DateTime<Local>,
+    pub label: String,
+    pub expression: String,
+    pub dice: Vec<u8>,
+    pub total: i32,
+}
+
+impl RollRecord {
+    pub fn new(
+        label: impl Into<String>,
+        expression: impl Into<String>,
+        dice: Vec<u8>,
+        total: i32,
+    ) -> Self {
+        Self {
+            timestamp: Local::now(),
+            label: label.into(),
+            expression: expression.into(),
+            dice,
+            total,
+        }
+    }
+    pub fn from_roll(label: impl Into<String>, expr: &DiceExpr, result: &RollResult) -> Self {
+        let dice = result.dice.iter().map(|die| die.value).collect();
+        Self::new(label, expr.to_string(), dice, result.total)
+    }
+}
+
+impl std::fmt::Display for RollRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {}: {} {:?} = {}",
+            self.timestamp.format("%Y-%m-%d %H:%M"),
+            self.label,
+            self.expression,
+            self.dice,
+            self.total
+        )
+    }
+}
+
+/// Append-only log of every roll, one JSON record per line.
+#[derive(Debug, Clone)]
+pub struct History {
+    path: PathBuf,
+}
+
+impl History {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// The history file in the user's data directory.
+    pub fn open_default() -> Self {
+        let dir = dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("danjon");
+        Self::new(dir.join("history.jsonl"))
+    }
+
+    pub fn append(&self, record: &RollRecord) -> anyhow::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&self.path)?;
+        // start a fresh line after a torn write, rather than gluing onto it
+        if file.metadata()?.len() > 0 {
+            let mut last = [0];
+            file.seek(SeekFrom::End(-1))?;
+            file.read_exact(&mut last)?;
+            if last != *b"\n" {
+                writeln!(file)?;
+            }
+        }
+        writeln!(file, "{}", serde_json::to_string(record)?)?;
+        Ok(())
+    }
+
+    pub fn load(&self) -> anyhow::Result<Vec<RollRecord>> {
+        let bytes = match std::fs::read(&self.path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err.into()),
+        };
+        let mut records = vec![];
+        // a torn write shouldn't lose every other roll
+        for (number, line) in (1..).zip(bytes.split(|byte| *byte == b'\n')) {
+            if line.trim_ascii().is_empty() {
+                continue;
+            }
+            match serde_json::from_slice(line) {
+                Ok(record) => records.push(record),
+                Err(err) => tracing::warn!(
+                    "Skipping line {} of {}: {}",
+                    number,
+                    self.path.display(),
+                    err
+                ),
+            }
+        }
+        Ok(records)
+    }
+}
+
+fn filter<'a>(records: &'a [RollRecord], query: &str) -> Vec<&'a RollRecord> {
+    let query = query.to_lowercase();
+    records
+        .iter()
+        .filter(|record| {
+            record.label.to_lowercase().contains(&query)
+                || record.expression.to_lowercase().contains(&query)
+        })
+        .collect()
+}
+
+fn to_csv(records: &[RollRecord]) -> String {
+    let escape = |field: &str| format!("\"{}\"", field.replace('"', "\"\""));
+    let rows = records.iter().map(|record| {
+        let dice = record
+            .dice
+            .iter()
+            .map(u8::to_string)
+            .collect::<Vec<_>>()
+            .join(" ");
+        format!(
+            "{},{},{},{},{}",
+            record.timestamp.to_rfc3339(),
+            escape(&record.label),
+            escape(&record.expression),
+            escape(&dice),
+            record.total
+        )
+    });
+    std::iter::once("timestamp,label,expression,dice,total".to_string())
+        .chain(rows)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::str::FromStr;
+
+    fn record(label: &str, expression: &str, dice: Vec<u8>, total: i32) -> RollRecord {
+        RollRecord {
+            timestamp: DateTime::from_str("2024-01-01T12:00:00+00:00").unwrap(),
+            label: label.to_string(),
+            expression: expression.to_string(),
+            dice,
+            total,
+        }
+    }
+
+    #[test_case]
+    fn test_history_filter_and_csv() -> TResult {
+        test(|| {
+            let records = vec![
+                record("HP level 2", "1d10 + 2", vec![7], 9),
+                record("Fireball", "8d6", vec![1, 2, 3, 4, 5, 6, 1, 2], 24),
+                record("HP level 3", "1d10 + 2", vec![3], 5),
+            ];
+            let matching = filter(&records, "hp");
+            assert_eq!(matching, vec![&records[0], &records[2]]);
+            assert_eq!(filter(&records, "8D6"), vec![&records[1]]);
+
+            let csv = to_csv(&records[..1]);
+            let lines = csv.lines().collect::<Vec<_>>();
+            assert_eq!(lines.len(), 2);
+            assert!(lines[1].ends_with(",\"HP level 2\",\"1d10 + 2\",\"7\",9"));
+            csv
+        })
+    }
+
+    #[test_case]
+    fn test_history_skips_torn_lines() -> TResult {
+        test(|| -> anyhow::Result<usize> {
+            let path = std::env::temp_dir().join(format!("danjon-{}.jsonl", std::process::id()));
+            let history = History::new(path.clone());
+            history.append(&record("Fireball", "8d6", vec![6; 8], 48))?;
+            std::fs::OpenOptions::new()
+                .append(true)
+                .open(&path)?
+                .write_all(b"{\"timestamp\":\"2024-01-01T1")?;
+            history.append(&record("Stealth", "1d20 + 5", vec![12], 17))?;
+            let labels = history
+                .load()?
+                .into_iter()
+                .map(|record| record.label)
+                .collect::<Vec<_>>();
+            std::fs::remove_file(path)?;
+            assert_eq!(labels, vec!["Fireball", "Stealth"]);
+            Ok(labels.len())
+        })
+    }
+}
@@ -1,133 +1,692 @@
-use std::iter::repeat;
-use std::ops::Div;
-
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 use strum::{Display, EnumIter, IntoEnumIterator};
 
-use crate::dnd::Class;
+use crate::character::Character;
+use crate::ctx::Ctx;
+use crate::dnd::{format_classes, Ability, AbilityScores, Class, ClassLevels, DiceExpr, DicePool};
+use crate::history::RollRecord;
 
 use crate::prelude::*;
 
-pub fn calculate_hp() -> anyhow::Result<()> {
-    let class = Class::prompt();
-    let level = input_map("Level: ", str::parse::<u32>);
-    let con_mod = input_map("Constiution modifier: ", str::parse::<u8>);
-    let has_tough = confirm("Do you have the Tough feat?");
-    let is_hill_dwarf = confirm("Are you a Hill Dwarf?");
-    let method = select("Choose a method:", Method::iter().collect());
+mod modifier;
+mod rest;
+mod tracker;
+
+pub use modifier::*;
+pub use rest::*;
+pub use tracker::*;
+
+#[derive(Debug, Default, clap::Args)]
+pub struct HpArgs {
+    /// Use a saved character instead of the other options
+    #[arg(long)]
+    character: Option<String>,
+    /// Your class, e.g. `fighter`; repeat it for each class if you're multiclassed
+    #[arg(long)]
+    class: Vec<Class>,
+    /// Your levels in each `--class`, in the same order
+    #[arg(long, requires = "class")]
+    level: Vec<u32>,
+    /// Your ability scores in STR, DEX, CON, INT, WIS, CHA order, e.g. `15,14,13,12,10,8`
+    #[arg(long)]
+    abilities: Option<AbilityScores>,
+    /// Your Constitution modifier
+    #[arg(
+        long = "con",
+        allow_negative_numbers = true,
+        value_parser = clap::value_parser!(i8).range(-5..=10)
+    )]
+    con_mod: Option<i8>,
+    /// Something else that adds to your HP, e.g. `tough` or `hill-dwarf`; repeat it for each
+    #[arg(long)]
+    modifier: Vec<HpModifier>,
+    /// Take the average at each level after the first
+    #[arg(long, conflicts_with = "rolled")]
+    average: bool,
+    /// Roll the hit dice at each level after the first
+    #[arg(long)]
+    rolled: bool,
+}
+
+pub fn calculate_hp(ctx: &mut Ctx, args: HpArgs) -> anyhow::Result<()> {
+    let method = match (args.average, args.rolled) {
+        (true, _) => Some(Method::Average),
+        (_, true) => Some(Method::Rolled),
+        _ => None,
+    };
+    if !args.level.is_empty() && args.level.len() != args.class.len() {
+        anyhow::bail!("give one --level for each --class");
+    }
+    let given = !args.level.is_empty() && (args.abilities.is_some() || args.con_mod.is_some());
+    let character = ctx.character(args.character.as_deref(), !given)?;
+    // the yes/no questions are only asked if we had to prompt for something else
+    let mut complete = character.is_some() || !args.level.is_empty() && method.is_some();
+
+    let classes = match (args.class.is_empty(), &character) {
+        (true, Some(character)) => character.classes.clone(),
+        (true, None) => ClassLevels::prompt(),
+        (false, _) => {
+            let levels = args
+                .level
+                .into_iter()
+                .map(Some)
+                .chain(std::iter::repeat(None));
+            args.class
+                .into_iter()
+                .zip(levels)
+                .map(|(class, levels)| {
+                    let levels = levels.unwrap_or_else(|| {
+                        input_map(&format!("Levels in {}: ", class), str::parse::<u32>)
+                    });
+                    ClassLevels { class, levels }
+                })
+                .collect()
+        }
+    };
+    let given = args
+        .abilities
+        .or_else(|| character.as_ref().map(|character| character.abilities));
+    let abilities = match (ctx.abilities(given), args.con_mod) {
+        (Some(abilities), None) => abilities,
+        (abilities, con_mod) => {
+            let mut abilities = abilities.unwrap_or_default();
+            complete &= abilities.set_or_prompt(Ability::Constitution, con_mod);
+            abilities
+        }
+    };
+    let mut modifiers = args.modifier;
+    match &character {
+        Some(character) => add_modifiers(&mut modifiers, character.hp_modifiers.clone()),
+        None if modifiers.is_empty() && !complete => modifiers = HpModifier::prompt_many(),
+        None => {}
+    }
+    let method = method.unwrap_or_else(|| select("Choose a method:", Method::iter().collect()));
 
     let hp = Hp {
-        class,
-        level,
-        con_mod,
-        has_tough,
-        is_hill_dwarf,
+        classes,
+        abilities,
+        modifiers,
         method,
     };
 
     tracing::debug!(?hp);
 
-    let hp = hp.calculate();
-    match hp {
-        HpResult::Rolled(hp, rolls) => {
+    let result = hp.calculate(&mut ctx.rng);
+    record_rolls(ctx, &result.levels, 1);
+
+    let report = HpReport { hp: &hp, result };
+    ctx.output(&report, |report| {
+        if report.result.classes.len() > 1 {
+            for class in &report.result.classes {
+                tracing::info!("{} {}: {} HP", class.class, class.levels, class.hp);
+            }
+        }
+        let rolls = report
+            .result
+            .classes
+            .iter()
+            .flat_map(|class| &class.rolls)
+            .collect::<Vec<_>>();
+        if !rolls.is_empty() {
             tracing::info!("Rolls: {:?}", rolls);
-            tracing::info!("HP: {}", hp);
         }
-        HpResult::Average(hp) => tracing::info!("HP: {}", hp),
+        tracing::info!("HP: {}", report.result.total);
+    })
+}
+
+#[derive(Debug, Default, clap::Args)]
+pub struct LevelUpArgs {
+    /// The saved character to level up
+    #[arg(long)]
+    character: Option<String>,
+    /// The class to take the new level in, e.g. `warlock`
+    #[arg(long)]
+    class: Option<Class>,
+    /// Take the average of the hit die
+    #[arg(long, conflicts_with = "rolled")]
+    average: bool,
+    /// Roll the hit die
+    #[arg(long)]
+    rolled: bool,
+    /// Your new Constitution score, e.g. `19`; a modifier can't tell 18 from 19
+    #[arg(long = "con-score", value_parser = clap::value_parser!(u8).range(1..=30))]
+    con: Option<u8>,
+    /// Something you gained that adds to your HP, e.g. `tough`; repeat it for each
+    #[arg(long)]
+    modifier: Vec<HpModifier>,
+}
+
+#[derive(Debug, Clone)]
+enum ClassChoice {
+    Existing(Class),
+    Multiclass,
+}
+
+impl std::fmt::Display for ClassChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClassChoice::Existing(class) => write!(f, "{}", class),
+            ClassChoice::Multiclass => write!(f, "Multiclass into a new class"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct LevelUpReport<'a> {
+    character: &'a str,
+    level: usize,
+    gained: &'a LevelHp,
+    hp: u32,
+    max_hp: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recalculated: Option<Recalculation>,
+}
+
+pub fn level_up(ctx: &mut Ctx, args: LevelUpArgs) -> anyhow::Result<()> {
+    let mut character = ctx
+        .characters
+        .load_or_select(args.character.as_deref(), "Who is levelling up?")?;
+    fill_hp_history(ctx, &mut character);
+    // the yes/no question is only asked if we had to prompt for something else
+    let complete =
+        args.character.is_some() && args.class.is_some() && (args.average || args.rolled);
+
+    let class = args.class.unwrap_or_else(|| {
+        let choices = character
+            .classes
+            .iter()
+            .map(|class| ClassChoice::Existing(class.class.clone()))
+            .chain(std::iter::once(ClassChoice::Multiclass))
+            .collect();
+        match select("Which class are you taking a level in?", choices) {
+            ClassChoice::Existing(class) => class,
+            ClassChoice::Multiclass => Class::prompt(),
+        }
+    });
+    let method = match (args.average, args.rolled) {
+        (true, _) => Method::Average,
+        (_, true) => Method::Rolled,
+        _ => select("Choose a method:", Method::iter().collect()),
+    };
+
+    let flags = args.con.is_some() || !args.modifier.is_empty();
+    let asked = !complete
+        && !flags
+        && confirm("Did your Constitution change, or did you gain something that adds HP?");
+    let recalculation = recalculate(&mut character, args.con, args.modifier, asked);
+    let recalculated = (!recalculation.changes.is_empty()).then_some(recalculation);
+
+    let hit_points = match (character.hp.is_empty(), method) {
+        (true, _) => HitPoints::Max,
+        (false, Method::Average) => HitPoints::Average,
+        (false, Method::Rolled) => HitPoints::Rolled(class.hit_dice().roll(&mut ctx.rng)),
+    };
+    let gained = Hp::from_character(&character, method).level(&class, hit_points);
+    match character
+        .classes
+        .iter_mut()
+        .find(|levels| levels.class == class)
+    {
+        Some(levels) => levels.levels += 1,
+        None => character.classes.push(ClassLevels { class, levels: 1 }),
+    }
+    character.hp.push(gained.clone());
+    // whatever max HP gained is gained as current HP too
+    if let Some(tracker) = &mut character.tracker {
+        tracker.apply(max_hp(&character.hp), HpEvent::LevelUp(gained.hp()));
+    }
+    record_rolls(
+        ctx,
+        &character.hp[character.hp.len() - 1..],
+        character.hp.len(),
+    );
+    ctx.characters.save(&character)?;
+
+    let report = LevelUpReport {
+        character: &character.name,
+        level: character.hp.len(),
+        gained: &gained,
+        hp: gained.hp(),
+        max_hp: max_hp(&character.hp),
+        recalculated,
+    };
+    ctx.output(&report, |report| {
+        if let Some(recalculated) = &report.recalculated {
+            recalculated.explain();
+        }
+        tracing::info!(
+            "{} reached level {} ({})",
+            report.character,
+            report.level,
+            format_classes(&character.classes)
+        );
+        tracing::info!("Gained {} HP, max HP is now {}", report.hp, report.max_hp);
+    })
+}
+
+#[derive(Debug, Default, clap::Args)]
+pub struct RecalculateArgs {
+    /// The saved character whose HP changed
+    #[arg(long)]
+    character: Option<String>,
+    /// Your new Constitution score, e.g. `19`; a modifier can't tell 18 from 19
+    #[arg(long = "con-score", value_parser = clap::value_parser!(u8).range(1..=30))]
+    con: Option<u8>,
+    /// Something you gained that adds to your HP, e.g. `tough`; repeat it for each
+    #[arg(long)]
+    modifier: Vec<HpModifier>,
+}
+
+#[derive(Debug, Serialize)]
+struct RecalculateReport<'a> {
+    character: &'a str,
+    #[serde(flatten)]
+    recalculated: &'a Recalculation,
+}
+
+/// Updates a saved character's max HP when their CON or HP bonuses change
+/// between levels, e.g. from an Amulet of Health.
+pub fn recalculate_hp(ctx: &mut Ctx, args: RecalculateArgs) -> anyhow::Result<()> {
+    let mut character = ctx
+        .characters
+        .load_or_select(args.character.as_deref(), "Whose HP changed?")?;
+    fill_hp_history(ctx, &mut character);
+
+    // a named character without any changes just has their HP brought up to date
+    let ask = args.character.is_none() && args.con.is_none() && args.modifier.is_empty();
+    let recalculation = recalculate(&mut character, args.con, args.modifier, ask);
+    ctx.characters.save(&character)?;
+
+    let report = RecalculateReport {
+        character: &character.name,
+        recalculated: &recalculation,
+    };
+    ctx.output(&report, |report| {
+        match report.recalculated.changes.is_empty() {
+            true => tracing::info!(
+                "{}'s max HP is still {}",
+                report.character,
+                report.recalculated.after
+            ),
+            false => report.recalculated.explain(),
+        }
+    })
+}
+
+/// Characters saved before HP was tracked per level are assumed to have
+/// taken the average.
+pub fn fill_hp_history(ctx: &mut Ctx, character: &mut Character) {
+    if character.hp.is_empty() {
+        tracing::info!(
+            "No HP history saved for {}, assuming they took the average so far",
+            character.name
+        );
+        character.hp = Hp::from_character(character, Method::Average).levels(&mut ctx.rng);
+    }
+}
+
+/// Applies a new CON score and newly gained HP modifiers to every level
+/// taken so far, asking for both if `ask` is set, and moves the tracker's
+/// current HP along with the max.
+fn recalculate(
+    character: &mut Character,
+    con: Option<u8>,
+    gained: Vec<HpModifier>,
+    ask: bool,
+) -> Recalculation {
+    match con {
+        Some(con) => character.abilities.set(Ability::Constitution, con),
+        None if ask => character.abilities.prompt(Ability::Constitution),
+        None => {}
+    }
+    let gained = match ask {
+        true => HpModifier::prompt_many(),
+        false => gained,
+    };
+    add_modifiers(&mut character.hp_modifiers, gained);
+    // the method only matters for new levels
+    let recalculation = Hp::from_character(character, Method::Average).recalculate(&character.hp);
+    character.hp = recalculation.levels.clone();
+    if let (Some(tracker), false) = (&mut character.tracker, recalculation.changes.is_empty()) {
+        tracker.apply(
+            recalculation.after,
+            HpEvent::Recalculated {
+                before: recalculation.before,
+                after: recalculation.after,
+            },
+        );
+    }
+    recalculation
+}
+
+/// Adds the modifiers in `new` that aren't in `modifiers` already.
+fn add_modifiers(modifiers: &mut Vec<HpModifier>, new: Vec<HpModifier>) {
+    for modifier in new {
+        if !modifiers.contains(&modifier) {
+            modifiers.push(modifier);
+        }
     }
+}
 
-    Ok(())
+/// Saves every rolled hit die to the roll history. `first_level` is the
+/// character level of `levels[0]`.
+pub fn record_rolls(ctx: &Ctx, levels: &[LevelHp], first_level: usize) {
+    for (level, level_hp) in (first_level..).zip(levels) {
+        if let HitPoints::Rolled(roll) = level_hp.hit_points {
+            let sign = if level_hp.con_mod < 0 { '-' } else { '+' };
+            ctx.record(RollRecord::new(
+                format!("HP level {}", level),
+                format!(
+                    "1d{} {} {}",
+                    u8::from(level_hp.class.hit_dice()),
+                    sign,
+                    level_hp.con_mod.unsigned_abs()
+                ),
+                vec![roll],
+                (i32::from(roll) + i32::from(level_hp.con_mod)).max(1),
+            ));
+        }
+    }
 }
 
-#[derive(Debug, Display, EnumIter, Clone)]
-enum Method {
+#[derive(Debug, Display, EnumIter, Clone, Copy, Serialize)]
+pub enum Method {
     Rolled,
     Average,
 }
 
-#[derive(Debug)]
-struct Hp {
-    class: Class,
-    level: u32,
-    con_mod: u8,
-    has_tough: bool,
-    is_hill_dwarf: bool,
+#[derive(Debug, Serialize)]
+pub struct Hp {
+    classes: Vec<ClassLevels>,
+    abilities: AbilityScores,
+    modifiers: Vec<HpModifier>,
     method: Method,
 }
 
-#[derive(Debug, PartialEq)]
-enum HpResult {
-    Rolled(f32, Vec<u8>),
-    Average(f32),
+/// What the hit die gave at a level, before CON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HitPoints {
+    /// Your first level always gets the whole hit die
+    Max,
+    Average,
+    Rolled(u8),
+}
+
+/// The HP gained at one level, as it was when the character took it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LevelHp {
+    pub class: Class,
+    pub hit_points: HitPoints,
+    pub con_mod: i8,
+    /// Extra HP on top of the hit die from [`HpModifier`]s; flat bonuses
+    /// count towards the first level
+    pub bonus: u32,
+}
+
+impl LevelHp {
+    fn new(class: &Class, hit_points: HitPoints, con_mod: i8, bonus: u32) -> Self {
+        Self {
+            class: class.clone(),
+            hit_points,
+            con_mod,
+            bonus,
+        }
+    }
+
+    fn hit_die(&self) -> u8 {
+        let faces = u8::from(self.class.hit_dice());
+        match self.hit_points {
+            HitPoints::Max => faces,
+            HitPoints::Average => 1 + faces.div_ceil(2),
+            HitPoints::Rolled(roll) => roll,
+        }
+    }
+
+    pub fn hp(&self) -> u32 {
+        // a level always grants at least 1 HP, however low your CON
+        let hp = (i32::from(self.hit_die()) + i32::from(self.con_mod)).max(1);
+        hp as u32 + self.bonus
+    }
+}
+
+/// Max HP before and after applying a new CON modifier or HP modifiers
+/// to every level taken so far.
+#[derive(Debug, Clone, Serialize)]
+pub struct Recalculation {
+    pub before: u32,
+    pub after: u32,
+    pub changes: Vec<HpChange>,
+    #[serde(skip)]
+    pub levels: Vec<LevelHp>,
+}
+
+/// One reason max HP changed, and by how much.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HpChange {
+    pub reason: String,
+    pub levels: u32,
+    pub hp: i64,
+}
+
+impl Recalculation {
+    pub fn explain(&self) {
+        for change in &self.changes {
+            tracing::info!(
+                "{} over {} levels: {:+} HP",
+                change.reason,
+                change.levels,
+                change.hp
+            );
+        }
+        tracing::info!("Max HP went from {} to {}", self.before, self.after);
+    }
+}
+
+/// Max HP is the sum of what every level gave.
+pub fn max_hp(levels: &[LevelHp]) -> u32 {
+    levels.iter().map(LevelHp::hp).sum()
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct HpResult {
+    total: u32,
+    classes: Vec<ClassHp>,
+    levels: Vec<LevelHp>,
+}
+
+/// The HP gained from the levels in one class.
+#[derive(Debug, PartialEq, Serialize)]
+struct ClassHp {
+    class: Class,
+    levels: u32,
+    hp: u32,
+    /// The hit die rolled for each level, bar your first
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    rolls: Vec<u8>,
+}
+
+impl HpResult {
+    fn from_levels(classes: &[ClassLevels], levels: Vec<LevelHp>) -> Self {
+        let breakdown = classes
+            .iter()
+            .map(|ClassLevels { class, levels }| ClassHp {
+                class: class.clone(),
+                levels: *levels,
+                hp: 0,
+                rolls: vec![],
+            })
+            .collect();
+        let init = HpResult {
+            total: 0,
+            classes: breakdown,
+            levels: vec![],
+        };
+        levels.into_iter().fold(init, |mut result, level| {
+            result.total += level.hp();
+            if let Some(class) = result
+                .classes
+                .iter_mut()
+                .find(|class| class.class == level.class)
+            {
+                class.hp += level.hp();
+                if let HitPoints::Rolled(roll) = level.hit_points {
+                    class.rolls.push(roll);
+                }
+            }
+            result.levels.push(level);
+            result
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct HpReport<'a> {
+    #[serde(flatten)]
+    hp: &'a Hp,
+    result: HpResult,
 }
 
 impl Hp {
-    fn calculate(&self) -> HpResult {
-        use rand::Rng;
-
-        let tough_value = if self.has_tough { 2 * self.level } else { 0 } as f32;
-        let hill_dwarf_value = if self.is_hill_dwarf { self.level } else { 0 } as f32;
-        let hit_dice = f32::from(self.class.hit_dice());
-
-        match self.method {
-            Method::Rolled => {
-                let rolls = repeat(()).take(self.level as usize - 1).map(|_| {
-                    let roll = rand::thread_rng().gen_range(1..=hit_dice as u8);
-                    tracing::trace!("Rolled a {}", roll);
-                    roll as f32 + self.con_mod as f32
-                });
-                let hp = hit_dice
-                    + self.con_mod as f32
-                    + tough_value
-                    + hill_dwarf_value
-                    + rolls.clone().sum::<f32>();
-                HpResult::Rolled(hp, rolls.map(|x| x as u8).collect())
+    pub fn from_character(character: &Character, method: Method) -> Self {
+        Self {
+            classes: character.classes.clone(),
+            abilities: character.abilities,
+            modifiers: character.hp_modifiers.clone(),
+            method,
+        }
+    }
+
+    fn con_mod(&self) -> i8 {
+        self.abilities.modifier(Ability::Constitution)
+    }
+
+    /// HP the modifiers add to a level in `class`.
+    fn bonus(&self, class: &Class, first: bool) -> u32 {
+        self.modifiers
+            .iter()
+            .map(|modifier| modifier.bonus(class, first))
+            .sum()
+    }
+
+    /// Works out every level from scratch, one class after another.
+    pub fn levels(&self, rng: &mut impl Rng) -> Vec<LevelHp> {
+        let mut history = vec![];
+        for (index, ClassLevels { class, levels }) in self.classes.iter().enumerate() {
+            // only the very first level of your first class gets the max hit die
+            let first = index == 0 && *levels > 0;
+            if first {
+                history.push(self.level(class, HitPoints::Max));
+            }
+            let remaining = levels - u32::from(first);
+            match self.method {
+                Method::Rolled => {
+                    let pool = DiceExpr::Roll(DicePool::new(remaining, class.hit_dice()));
+                    for die in pool.roll(rng).dice {
+                        history.push(self.level(class, HitPoints::Rolled(die.value)));
+                    }
+                }
+                Method::Average => {
+                    for _ in 0..remaining {
+                        history.push(self.level(class, HitPoints::Average));
+                    }
+                }
+            }
+        }
+        history
+    }
+
+    /// Re-applies the current CON modifier and per-level bonus to every level
+    /// in `levels`, keeping the hit die each one got.
+    pub fn recalculate(&self, levels: &[LevelHp]) -> Recalculation {
+        let mut changes: Vec<HpChange> = vec![];
+        let mut change = |reason: String, hp: i64| {
+            if hp == 0 {
+                return;
             }
-            Method::Average => {
-                let avg = 1.0 + hit_dice.div(2.0).ceil();
-
-                tracing::trace!(
-                    "lvl 1 ({} hit dice + {} CON) = {}",
-                    hit_dice,
-                    self.con_mod,
-                    hit_dice + self.con_mod as f32
+            match changes.iter_mut().find(|change| change.reason == reason) {
+                Some(change) => {
+                    change.levels += 1;
+                    change.hp += hp;
+                }
+                None => changes.push(HpChange {
+                    reason,
+                    levels: 1,
+                    hp,
+                }),
+            }
+        };
+
+        let recalculated = levels
+            .iter()
+            .map(|level| {
+                let con = LevelHp {
+                    con_mod: self.con_mod(),
+                    ..level.clone()
+                };
+                change(
+                    format!("CON modifier {:+} -> {:+}", level.con_mod, self.con_mod()),
+                    i64::from(con.hp()) - i64::from(level.hp()),
                 );
-                tracing::trace!(
-                    "HP at subsequent levels = {}",
-                    (avg + self.con_mod as f32)
-                        + f32::from(self.has_tough)
-                        + f32::from(self.is_hill_dwarf) * 2.0
+                let bonus = LevelHp {
+                    bonus: self.bonus(&level.class, level.hit_points == HitPoints::Max),
+                    ..con.clone()
+                };
+                change(
+                    format!("Bonus HP {} -> {}", level.bonus, bonus.bonus),
+                    i64::from(bonus.hp()) - i64::from(con.hp()),
                 );
+                bonus
+            })
+            .collect::<Vec<_>>();
 
-                let hp = hit_dice
-                    + self.con_mod as f32
-                    + (avg + self.con_mod as f32) * (self.level - 1) as f32
-                    + tough_value
-                    + hill_dwarf_value;
-                HpResult::Average(hp)
-            }
+        Recalculation {
+            before: max_hp(levels),
+            after: max_hp(&recalculated),
+            changes,
+            levels: recalculated,
         }
     }
+
+    /// The next level in `class`, with the current CON modifier.
+    fn level(&self, class: &Class, hit_points: HitPoints) -> LevelHp {
+        let first = hit_points == HitPoints::Max;
+        LevelHp::new(class, hit_points, self.con_mod(), self.bonus(class, first))
+    }
+
+    fn calculate(&self, rng: &mut impl Rng) -> HpResult {
+        HpResult::from_levels(&self.classes, self.levels(rng))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use pretty_assertions::assert_eq;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn con(modifier: i8) -> AbilityScores {
+        AbilityScores {
+            constitution: crate::dnd::score_for(modifier),
+            ..Default::default()
+        }
+    }
 
     #[test_case]
     fn test_hp_barbarian() -> TResult {
         test(|| -> anyhow::Result<HpResult> {
             let hp = Hp {
-                class: Class::Barbarian,
-                level: 1,
-                con_mod: 2,
-                has_tough: false,
-                is_hill_dwarf: false,
+                classes: vec![ClassLevels {
+                    class: Class::Barbarian,
+                    levels: 1,
+                }],
+                abilities: con(2),
+                modifiers: vec![],
                 method: Method::Average,
             };
 
-            let hp = hp.calculate();
-            assert_eq!(hp, HpResult::Average(14.0));
+            let hp = hp.calculate(&mut StdRng::seed_from_u64(0));
+            assert_eq!(hp.total, 14);
             Ok(hp)
         })
     }
@@ -136,15 +695,16 @@ mod tests {
     fn test_calculate_hp_fighter() -> TResult {
         test(|| {
             let hp = Hp {
-                class: Class::Fighter,
-                level: 5,
-                con_mod: 2,
-                has_tough: false,
-                is_hill_dwarf: false,
+                classes: vec![ClassLevels {
+                    class: Class::Fighter,
+                    levels: 5,
+                }],
+                abilities: con(2),
+                modifiers: vec![],
                 method: Method::Average,
             };
-            let hp = hp.calculate();
-            assert_eq!(hp, HpResult::Average(44.0));
+            let hp = hp.calculate(&mut StdRng::seed_from_u64(0));
+            assert_eq!(hp.total, 44);
             hp
         })
     }
@@ -153,16 +713,193 @@ mod tests {
     fn test_calculate_hp_wizard() -> TResult {
         test(|| {
             let hp = Hp {
-                class: Class::Wizard,
-                level: 3,
-                con_mod: 1,
-                has_tough: true,
-                is_hill_dwarf: true,
+                classes: vec![ClassLevels {
+                    class: Class::Wizard,
+                    levels: 3,
+                }],
+                abilities: con(1),
+                modifiers: vec![
+                    KnownModifier::Tough.modifier(),
+                    KnownModifier::HillDwarf.modifier(),
+                ],
                 method: Method::Average,
             };
-            let hp = hp.calculate();
-            assert_eq!(hp, HpResult::Average(26.0));
+            let hp = hp.calculate(&mut StdRng::seed_from_u64(0));
+            assert_eq!(hp.total, 26);
             hp
         })
     }
+
+    #[test_case]
+    fn test_calculate_hp_rolled_seeded() -> TResult {
+        test(|| {
+            let hp = Hp {
+                classes: vec![ClassLevels {
+                    class: Class::Fighter,
+                    levels: 5,
+                }],
+                abilities: con(2),
+                modifiers: vec![],
+                method: Method::Rolled,
+            };
+            let rolled = hp.calculate(&mut StdRng::seed_from_u64(42));
+            assert_eq!(rolled, hp.calculate(&mut StdRng::seed_from_u64(42)));
+            assert_eq!(rolled.total, 37);
+            assert_eq!(rolled.classes[0].rolls, vec![2, 6, 3, 6]);
+            rolled
+        })
+    }
+
+    #[test_case]
+    fn test_calculate_hp_negative_con() -> TResult {
+        test(|| {
+            let hp = Hp {
+                classes: vec![ClassLevels {
+                    class: Class::Wizard,
+                    levels: 4,
+                }],
+                abilities: con(-1),
+                modifiers: vec![],
+                method: Method::Average,
+            };
+            let hp = hp.calculate(&mut StdRng::seed_from_u64(0));
+            assert_eq!(hp.total, 5 + 3 * 3);
+            hp
+        })
+    }
+
+    #[test_case]
+    fn test_calculate_hp_gains_at_least_one_per_level() -> TResult {
+        test(|| {
+            let hp = Hp {
+                classes: vec![ClassLevels {
+                    class: Class::Sorcerer,
+                    levels: 6,
+                }],
+                abilities: con(-5),
+                modifiers: vec![KnownModifier::Tough.modifier()],
+                method: Method::Rolled,
+            };
+            let hp = hp.calculate(&mut StdRng::seed_from_u64(1));
+            // a d6 with -5 CON is at most 1 HP, and some levels would go below that
+            assert_eq!(hp.total, 6 + 12);
+            assert_eq!(hp.classes[0].rolls, vec![5, 6, 5, 5, 2]);
+            hp
+        })
+    }
+
+    #[test_case]
+    fn test_calculate_hp_multiclass() -> TResult {
+        test(|| {
+            let hp = Hp {
+                classes: vec![
+                    ClassLevels {
+                        class: Class::Fighter,
+                        levels: 2,
+                    },
+                    ClassLevels {
+                        class: Class::Warlock,
+                        levels: 5,
+                    },
+                ],
+                abilities: con(2),
+                modifiers: vec![KnownModifier::Tough.modifier()],
+                method: Method::Average,
+            };
+            let hp = hp.calculate(&mut StdRng::seed_from_u64(0));
+            // the fighter's d10 is maxed at level 1, the warlock levels average their d8
+            let hp = hp.classes.iter().map(|class| class.hp).collect::<Vec<_>>();
+            assert_eq!(hp, vec![12 + 8 + 4, 7 * 5 + 10]);
+            hp
+        })
+    }
+
+    #[test_case]
+    fn test_level_up_matches_calculate() -> TResult {
+        test(|| {
+            let mut hp = Hp {
+                classes: vec![ClassLevels {
+                    class: Class::Fighter,
+                    levels: 2,
+                }],
+                abilities: con(1),
+                modifiers: vec![KnownModifier::HillDwarf.modifier()],
+                method: Method::Average,
+            };
+            let mut history = hp.levels(&mut StdRng::seed_from_u64(0));
+            history.push(hp.level(&Class::Warlock, HitPoints::Average));
+            hp.classes.push(ClassLevels {
+                class: Class::Warlock,
+                levels: 1,
+            });
+            let calculated = hp.calculate(&mut StdRng::seed_from_u64(0));
+            assert_eq!(max_hp(&history), calculated.total);
+            assert_eq!(history, calculated.levels);
+            max_hp(&history)
+        })
+    }
+
+    #[test_case]
+    fn test_recalculate_retroactively() -> TResult {
+        test(|| {
+            let mut hp = Hp {
+                classes: vec![ClassLevels {
+                    class: Class::Wizard,
+                    levels: 4,
+                }],
+                abilities: con(-1),
+                modifiers: vec![],
+                method: Method::Average,
+            };
+            let levels = hp.levels(&mut StdRng::seed_from_u64(0));
+            // an Amulet of Health sets CON to 19, and the wizard picks up Tough
+            hp.abilities.set(Ability::Constitution, 19);
+            hp.modifiers.push(KnownModifier::Tough.modifier());
+            let recalculation = hp.recalculate(&levels);
+            assert_eq!(recalculation.before, 5 + 3 * 3);
+            assert_eq!(recalculation.after, 10 + 3 * 8 + 4 * 2);
+            let changes = recalculation
+                .changes
+                .iter()
+                .map(|change| (change.levels, change.hp))
+                .collect::<Vec<_>>();
+            assert_eq!(changes, vec![(4, 5 * 4), (4, 2 * 4)]);
+            changes
+        })
+    }
+
+    #[test_case]
+    fn test_calculate_hp_modifiers() -> TResult {
+        test(|| {
+            let hp = Hp {
+                classes: vec![
+                    ClassLevels {
+                        class: Class::Sorcerer,
+                        levels: 3,
+                    },
+                    ClassLevels {
+                        class: Class::Warlock,
+                        levels: 2,
+                    },
+                ],
+                abilities: con(0),
+                modifiers: vec![
+                    KnownModifier::DraconicResilience.modifier(),
+                    KnownModifier::BoonOfFortitude.modifier(),
+                    "hill-dwarf".parse().unwrap(),
+                ],
+                method: Method::Average,
+            };
+            let hp = hp.calculate(&mut StdRng::seed_from_u64(0));
+            let bonuses = hp
+                .levels
+                .iter()
+                .map(|level| level.bonus)
+                .collect::<Vec<_>>();
+            // Draconic Resilience only counts sorcerer levels, the boon only counts once
+            assert_eq!(bonuses, vec![42, 2, 2, 1, 1]);
+            assert_eq!(hp.total, 6 + 4 * 2 + 5 * 2 + 42 + 2 + 2 + 1 + 1);
+            bonuses
+        })
+    }
 }
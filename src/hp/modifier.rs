@@ -0,0 +1,156 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumIter, IntoEnumIterator};
+
+use crate::dnd::Class;
+
+use crate::prelude::*;
+
+/// How much HP a modifier adds.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HpBonus {
+    /// Added at every level, whatever the class
+    PerLevel(u32),
+    /// Added once
+    Flat(u32),
+    /// Added at every level of one class
+    PerClassLevel(Class, u32),
+}
+
+/// Anything that adds to max HP on top of hit dice and CON, like the Tough
+/// feat.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HpModifier {
+    pub name: String,
+    pub bonus: HpBonus,
+}
+
+impl HpModifier {
+    pub fn new(name: impl Into<String>, bonus: HpBonus) -> Self {
+        Self {
+            name: name.into(),
+            bonus,
+        }
+    }
+
+    /// What the modifier adds to a level taken in `class`; flat bonuses
+    /// count towards the `first` level.
+    pub fn bonus(&self, class: &Class, first: bool) -> u32 {
+        match &self.bonus {
+            HpBonus::PerLevel(hp) => *hp,
+            HpBonus::Flat(hp) if first => *hp,
+            HpBonus::Flat(_) => 0,
+            HpBonus::PerClassLevel(bonus_class, hp) if bonus_class == class => *hp,
+            HpBonus::PerClassLevel(..) => 0,
+        }
+    }
+
+    /// Asks for a homebrew modifier.
+    pub fn prompt() -> HpModifier {
+        let name = input("Name of the homebrew HP bonus: ");
+        let kind = select("How does it add HP?", BonusKind::iter().collect());
+        let hp = input_map("How much HP does it add? ", str::parse::<u32>);
+        let bonus = match kind {
+            BonusKind::PerLevel => HpBonus::PerLevel(hp),
+            BonusKind::Flat => HpBonus::Flat(hp),
+            BonusKind::PerClassLevel => HpBonus::PerClassLevel(Class::prompt(), hp),
+        };
+        HpModifier::new(name, bonus)
+    }
+
+    /// Lets the user pick any number of modifiers from the catalogue, plus
+    /// homebrew ones.
+    pub fn prompt_many() -> Vec<HpModifier> {
+        let choices = KnownModifier::iter()
+            .map(|known| ModifierChoice::Known(known.modifier()))
+            .chain(std::iter::once(ModifierChoice::Homebrew))
+            .collect();
+        let mut modifiers = vec![];
+        for choice in multi_select("Anything else that adds to your HP?", choices) {
+            match choice {
+                ModifierChoice::Known(modifier) => modifiers.push(modifier),
+                ModifierChoice::Homebrew => loop {
+                    modifiers.push(HpModifier::prompt());
+                    if !confirm("Add another homebrew HP bonus?") {
+                        break;
+                    }
+                },
+            }
+        }
+        modifiers
+    }
+}
+
+impl std::fmt::Display for HpModifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.bonus {
+            HpBonus::PerLevel(hp) => write!(f, "{} (+{} per level)", self.name, hp),
+            HpBonus::Flat(hp) => write!(f, "{} (+{})", self.name, hp),
+            HpBonus::PerClassLevel(class, hp) => {
+                write!(f, "{} (+{} per {} level)", self.name, hp, class)
+            }
+        }
+    }
+}
+
+/// Only the catalogue can be named on the command line, e.g. `--modifier tough`.
+impl FromStr for HpModifier {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        from_name::<KnownModifier>(s)
+            .map(|known| known.modifier())
+            .ok_or_else(|| anyhow::anyhow!("unknown HP bonus '{}'", s))
+    }
+}
+
+/// The built-in catalogue.
+#[derive(Debug, Clone, Copy, Display, EnumIter)]
+pub enum KnownModifier {
+    Tough,
+    #[strum(serialize = "Hill Dwarf")]
+    HillDwarf,
+    #[strum(serialize = "Draconic Resilience")]
+    DraconicResilience,
+    #[strum(serialize = "Boon of Fortitude")]
+    BoonOfFortitude,
+}
+
+impl KnownModifier {
+    pub fn modifier(&self) -> HpModifier {
+        let bonus = match self {
+            KnownModifier::Tough => HpBonus::PerLevel(2),
+            // Dwarven Toughness
+            KnownModifier::HillDwarf => HpBonus::PerLevel(1),
+            KnownModifier::DraconicResilience => HpBonus::PerClassLevel(Class::Sorcerer, 1),
+            KnownModifier::BoonOfFortitude => HpBonus::Flat(40),
+        };
+        HpModifier::new(self.to_string(), bonus)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Display, EnumIter)]
+enum BonusKind {
+    #[strum(serialize = "Every level")]
+    PerLevel,
+    #[strum(serialize = "Once")]
+    Flat,
+    #[strum(serialize = "Every level in one class")]
+    PerClassLevel,
+}
+
+#[derive(Debug, Clone)]
+enum ModifierChoice {
+    Known(HpModifier),
+    Homebrew,
+}
+
+impl std::fmt::Display for ModifierChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModifierChoice::Known(modifier) => write!(f, "{}", modifier),
+            ModifierChoice::Homebrew => write!(f, "Homebrew"),
+        }
+    }
+}
@@ -0,0 +1,305 @@
+use serde::Serialize;
+use strum::{Display, EnumIter, IntoEnumIterator};
+
+use crate::ctx::Ctx;
+use crate::dnd::{Ability, ClassLevels, Dice, DiceExpr, DicePool};
+use crate::history::RollRecord;
+use crate::hp::{load_tracker, Condition, HpEvent, HpTracker};
+
+use crate::prelude::*;
+
+#[derive(Debug, Default, clap::Args)]
+pub struct RestArgs {
+    /// The saved character who is resting
+    #[arg(long)]
+    character: Option<String>,
+    #[command(subcommand)]
+    rest: Option<Rest>,
+}
+
+#[derive(Debug, Clone, Copy, clap::Subcommand)]
+enum Rest {
+    /// Spend hit dice, healing what they roll plus your CON modifier
+    Short {
+        /// How many hit dice to spend; asks one at a time if left out
+        count: Option<u32>,
+        /// The size of hit die to spend, e.g. `d10`; the biggest you have left by default
+        #[arg(long)]
+        dice: Option<Dice>,
+    },
+    /// Regain all your HP and half your hit dice
+    Long,
+}
+
+pub fn rest(ctx: &mut Ctx, args: RestArgs) -> anyhow::Result<()> {
+    let (mut character, max, mut tracker) =
+        load_tracker(ctx, args.character.as_deref(), "Who is resting?")?;
+    match tracker.condition() {
+        Condition::Dead => anyhow::bail!("{} is dead, resting won't help", character.name),
+        Condition::Dying => anyhow::bail!("{} is dying, stabilize them first", character.name),
+        _ => {}
+    }
+    let rest =
+        args.rest.unwrap_or_else(
+            || match select("What kind of rest?", Action::iter().collect()) {
+                Action::Short => Rest::Short {
+                    count: None,
+                    dice: None,
+                },
+                Action::Long => Rest::Long,
+            },
+        );
+
+    let before = tracker.current;
+    let con_mod = character.abilities.modifier(Ability::Constitution);
+    match rest {
+        Rest::Short {
+            count: Some(count),
+            dice,
+        } => {
+            let available: u32 = HitDice::of(&character.classes, &tracker)
+                .iter()
+                .filter(|hit_dice| dice.is_none_or(|dice| hit_dice.dice == dice))
+                .map(|hit_dice| hit_dice.available)
+                .sum();
+            if available < count {
+                anyhow::bail!("{} only has {} hit dice left", character.name, available);
+            }
+            for _ in 0..count {
+                let Some(hit_dice) =
+                    HitDice::of(&character.classes, &tracker)
+                        .into_iter()
+                        .find(|hit_dice| {
+                            hit_dice.available > 0 && dice.is_none_or(|dice| hit_dice.dice == dice)
+                        })
+                else {
+                    break;
+                };
+                spend_hit_die(ctx, &mut tracker, max, hit_dice.dice, con_mod);
+            }
+        }
+        Rest::Short { count: None, .. } => loop {
+            let choices: Vec<_> = HitDice::of(&character.classes, &tracker)
+                .into_iter()
+                .filter(|hit_dice| hit_dice.available > 0)
+                .map(HitDieChoice::Spend)
+                .collect();
+            if choices.is_empty() {
+                tracing::info!("{} has no hit dice left", character.name);
+                break;
+            }
+            let prompt = format!(
+                "{}/{} HP, spend a hit die?",
+                tracker.current,
+                tracker.max(max)
+            );
+            let choices = choices
+                .into_iter()
+                .chain(std::iter::once(HitDieChoice::Done))
+                .collect();
+            match select(&prompt, choices) {
+                HitDieChoice::Spend(hit_dice) => {
+                    spend_hit_die(ctx, &mut tracker, max, hit_dice.dice, con_mod)
+                }
+                HitDieChoice::Done => break,
+            }
+        },
+        Rest::Long => {
+            if tracker.is_unconscious() {
+                anyhow::bail!(
+                    "{} needs at least 1 HP to benefit from a long rest",
+                    character.name
+                );
+            }
+            let levels: u32 = character.classes.iter().map(|class| class.levels).sum();
+            tracker.apply(
+                max,
+                HpEvent::LongRest {
+                    hit_dice: (levels / 2).max(1),
+                },
+            );
+        }
+    }
+
+    let report = RestReport {
+        character: &character.name,
+        healed: tracker.current.saturating_sub(before),
+        current: tracker.current,
+        max: tracker.max(max),
+        hit_dice: HitDice::of(&character.classes, &tracker),
+    };
+    character.tracker = Some(tracker);
+    ctx.characters.save(&character)?;
+    ctx.output(&report, |report| {
+        tracing::info!(
+            "{} regained {} HP, {}/{} HP",
+            report.character,
+            report.healed,
+            report.current,
+            report.max
+        );
+        for hit_dice in &report.hit_dice {
+            println!("{}", hit_dice);
+        }
+    })
+}
+
+/// Rolls one hit die through the dice roller and heals with it.
+fn spend_hit_die(ctx: &mut Ctx, tracker: &mut HpTracker, max: u32, dice: Dice, con_mod: i8) {
+    let expr = DiceExpr::Roll(DicePool::new(1, dice));
+    let result = expr.roll(&mut ctx.rng);
+    ctx.record(RollRecord::from_roll("Hit die", &expr, &result));
+    tracker.apply(
+        max,
+        HpEvent::HitDie {
+            dice,
+            roll: result.total as u8,
+            con_mod,
+        },
+    );
+}
+
+/// A character's hit dice of one size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct HitDice {
+    pub dice: Dice,
+    pub available: u32,
+    pub total: u32,
+}
+
+impl HitDice {
+    /// One pool per size of hit die across `classes`, biggest first, so a
+    /// Fighter / Wizard has their d10s and d6s kept apart.
+    pub fn of(classes: &[ClassLevels], tracker: &HpTracker) -> Vec<HitDice> {
+        let mut pools: Vec<HitDice> = vec![];
+        for class in classes {
+            let dice = class.class.hit_dice();
+            match pools.iter_mut().find(|pool| pool.dice == dice) {
+                Some(pool) => pool.total += class.levels,
+                None => pools.push(HitDice {
+                    dice,
+                    available: 0,
+                    total: class.levels,
+                }),
+            }
+        }
+        for pool in &mut pools {
+            let spent = tracker
+                .spent_hit_dice
+                .get(&u8::from(pool.dice))
+                .copied()
+                .unwrap_or(0);
+            pool.available = pool.total.saturating_sub(spent);
+        }
+        pools.sort_by_key(|pool| std::cmp::Reverse(u8::from(pool.dice)));
+        pools
+    }
+}
+
+impl std::fmt::Display for HitDice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} of {} hit dice left",
+            self.dice, self.available, self.total
+        )
+    }
+}
+
+#[derive(Debug, Display, EnumIter, Clone)]
+enum Action {
+    #[strum(serialize = "Short rest")]
+    Short,
+    #[strum(serialize = "Long rest")]
+    Long,
+}
+
+#[derive(Debug, Clone)]
+enum HitDieChoice {
+    Spend(HitDice),
+    Done,
+}
+
+impl std::fmt::Display for HitDieChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HitDieChoice::Spend(hit_dice) => write!(f, "{}", hit_dice),
+            HitDieChoice::Done => write!(f, "Done resting"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct RestReport<'a> {
+    character: &'a str,
+    healed: u32,
+    current: u32,
+    max: u32,
+    hit_dice: Vec<HitDice>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dnd::Class;
+    use pretty_assertions::assert_eq;
+
+    #[test_case]
+    fn test_hit_dice_pools() -> TResult {
+        test(|| {
+            let classes = vec![
+                ClassLevels {
+                    class: Class::Wizard,
+                    levels: 3,
+                },
+                ClassLevels {
+                    class: Class::Fighter,
+                    levels: 2,
+                },
+                ClassLevels {
+                    class: Class::Sorcerer,
+                    levels: 2,
+                },
+            ];
+            let mut tracker = HpTracker::new(40);
+            tracker.current = 20;
+            for (dice, roll) in [(Dice::D10, 3), (Dice::D10, 7), (Dice::D6, 2), (Dice::D6, 1)] {
+                tracker.apply(
+                    40,
+                    HpEvent::HitDie {
+                        dice,
+                        roll,
+                        con_mod: -2,
+                    },
+                );
+            }
+            // a roll below the CON penalty heals nothing rather than hurting
+            assert_eq!(tracker.current, 26);
+            let pools = HitDice::of(&classes, &tracker);
+            assert_eq!(
+                pools,
+                vec![
+                    HitDice {
+                        dice: Dice::D10,
+                        available: 0,
+                        total: 2
+                    },
+                    HitDice {
+                        dice: Dice::D6,
+                        available: 3,
+                        total: 5
+                    },
+                ]
+            );
+            // 7 levels regain 3 hit dice, the d10s first
+            tracker.apply(40, HpEvent::LongRest { hit_dice: 3 });
+            assert_eq!(tracker.current, 40);
+            let pools = HitDice::of(&classes, &tracker);
+            assert_eq!(
+                pools.iter().map(|pool| pool.available).collect::<Vec<_>>(),
+                vec![2, 4]
+            );
+            pools.len()
+        })
+    }
+}
@@ -0,0 +1,679 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumIter, IntoEnumIterator};
+
+use crate::character::Character;
+use crate::ctx::Ctx;
+use crate::dnd::{DamageType, Dice, DiceExpr, DicePool};
+use crate::history::RollRecord;
+use crate::hp::{fill_hp_history, max_hp};
+
+use crate::prelude::*;
+
+#[derive(Debug, Default, clap::Args)]
+pub struct TrackArgs {
+    /// The saved character to track
+    #[arg(long)]
+    character: Option<String>,
+    #[command(subcommand)]
+    event: Option<TrackAction>,
+}
+
+#[derive(Debug, Clone, Copy, clap::Subcommand)]
+enum TrackAction {
+    /// Take damage; temporary HP soaks it up first
+    Damage {
+        amount: u32,
+        /// The type of damage, e.g. `fire`, to apply resistances and the like
+        #[arg(long = "type")]
+        damage_type: Option<DamageType>,
+        /// It was a critical hit, which counts twice against death saves
+        #[arg(long)]
+        crit: bool,
+    },
+    /// Roll a death saving throw
+    DeathSave,
+    /// Become stable at 0 HP, e.g. from a Medicine check
+    Stabilize,
+    /// Regain HP, up to your max
+    Heal { amount: u32 },
+    /// Gain temporary HP, keeping whichever is higher
+    Temp { amount: u32 },
+    /// Lower your max HP, e.g. from a wraith's Life Drain
+    ReduceMax { amount: u32 },
+    /// Undo every reduction to your max HP
+    RestoreMax,
+    /// Show current HP
+    Status,
+    /// Show every change made so far
+    Log,
+}
+
+pub fn track_hp(ctx: &mut Ctx, args: TrackArgs) -> anyhow::Result<()> {
+    let (mut character, max, mut tracker) =
+        load_tracker(ctx, args.character.as_deref(), "Whose HP are we tracking?")?;
+
+    let interactive = args.event.is_none();
+    loop {
+        let action = match args.event {
+            Some(action) => action,
+            None => match select("What happened?", Action::iter().collect()) {
+                Action::Damage => TrackAction::Damage {
+                    amount: input_map("How much damage? ", str::parse::<u32>),
+                    damage_type: input_map_opt(
+                        "Damage type (leave empty if it doesn't matter): ",
+                        DamageType::from_str,
+                    ),
+                    // crits only matter for death saves
+                    crit: tracker.is_unconscious() && confirm("Was it a critical hit?"),
+                },
+                Action::DeathSave => TrackAction::DeathSave,
+                Action::Stabilize => TrackAction::Stabilize,
+                Action::Heal => TrackAction::Heal {
+                    amount: input_map("How much healing? ", str::parse::<u32>),
+                },
+                Action::Temp => TrackAction::Temp {
+                    amount: input_map("How much temporary HP? ", str::parse::<u32>),
+                },
+                Action::ReduceMax => TrackAction::ReduceMax {
+                    amount: input_map("Reduce max HP by how much? ", str::parse::<u32>),
+                },
+                Action::RestoreMax => TrackAction::RestoreMax,
+                Action::Log => TrackAction::Log,
+                Action::Done => break,
+            },
+        };
+
+        let event = match action {
+            TrackAction::Damage {
+                amount,
+                damage_type,
+                crit,
+            } => Some(HpEvent::Damage {
+                amount,
+                damage_type,
+                taken: character.defenses.adjust(amount, damage_type),
+                critical: crit,
+            }),
+            TrackAction::DeathSave if !tracker.is_dying() => {
+                tracing::warn!("{} isn't dying", character.name);
+                None
+            }
+            TrackAction::DeathSave => {
+                let expr = DiceExpr::Roll(DicePool::new(1, Dice::D20));
+                let result = expr.roll(&mut ctx.rng);
+                ctx.record(RollRecord::from_roll("Death save", &expr, &result));
+                Some(HpEvent::DeathSave(result.total as u8))
+            }
+            TrackAction::Stabilize => Some(HpEvent::Stabilize),
+            TrackAction::Heal { amount } => Some(HpEvent::Heal(amount)),
+            TrackAction::Temp { amount } => Some(HpEvent::Temp(amount)),
+            TrackAction::ReduceMax { amount } => Some(HpEvent::ReduceMax(amount)),
+            TrackAction::RestoreMax => Some(HpEvent::RestoreMax),
+            TrackAction::Status => None,
+            TrackAction::Log => {
+                ctx.output(&tracker.log, |log| {
+                    for entry in log {
+                        println!("{}", entry);
+                    }
+                })?;
+                if interactive {
+                    continue;
+                }
+                break;
+            }
+        };
+        if let Some(event) = event {
+            let entry = tracker.apply(max, event);
+            tracing::debug!(?entry);
+            character.tracker = Some(tracker.clone());
+            ctx.characters.save(&character)?;
+        }
+
+        let report = TrackerReport {
+            character: &character.name,
+            max: tracker.max(max),
+            current: tracker.current,
+            temp: tracker.temp,
+            condition: tracker.condition(),
+            death_saves: tracker.is_dying().then_some(tracker.death_saves),
+        };
+        ctx.output(&report, |report| println!("{}", report))?;
+        if !interactive {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Loads a character along with their max HP and tracker, starting a new
+/// tracker at full HP if they don't have one yet.
+pub fn load_tracker(
+    ctx: &mut Ctx,
+    name: Option<&str>,
+    prompt: &str,
+) -> anyhow::Result<(Character, u32, HpTracker)> {
+    let mut character = ctx.characters.load_or_select(name, prompt)?;
+    fill_hp_history(ctx, &mut character);
+    let max = max_hp(&character.hp);
+    let tracker = character
+        .tracker
+        .take()
+        .unwrap_or_else(|| HpTracker::new(max));
+    Ok((character, max, tracker))
+}
+
+#[derive(Debug, Display, EnumIter, Clone)]
+enum Action {
+    #[strum(serialize = "Take damage")]
+    Damage,
+    #[strum(serialize = "Roll a death save")]
+    DeathSave,
+    #[strum(serialize = "Stabilize")]
+    Stabilize,
+    #[strum(serialize = "Heal")]
+    Heal,
+    #[strum(serialize = "Gain temporary HP")]
+    Temp,
+    #[strum(serialize = "Reduce max HP")]
+    ReduceMax,
+    #[strum(serialize = "Restore max HP")]
+    RestoreMax,
+    #[strum(serialize = "Show the log")]
+    Log,
+    #[strum(serialize = "Done")]
+    Done,
+}
+
+#[derive(Debug, Serialize)]
+struct TrackerReport<'a> {
+    character: &'a str,
+    max: u32,
+    current: u32,
+    temp: u32,
+    condition: Condition,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    death_saves: Option<DeathSaves>,
+}
+
+impl std::fmt::Display for TrackerReport<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}/{} HP", self.character, self.current, self.max)?;
+        if self.temp > 0 {
+            write!(f, " + {} temporary", self.temp)?;
+        }
+        match self.condition {
+            Condition::Conscious => {}
+            condition => write!(f, ", {}", condition)?,
+        }
+        if let Some(saves) = self.death_saves {
+            write!(
+                f,
+                " ({} successes, {} failures)",
+                saves.successes, saves.failures
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Something that changed a character's HP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HpEvent {
+    /// `amount` damage was dealt, `taken` is what's left after resistances
+    /// and the like
+    Damage {
+        amount: u32,
+        damage_type: Option<DamageType>,
+        taken: u32,
+        #[serde(default)]
+        critical: bool,
+    },
+    /// A death saving throw, with what the d20 rolled
+    DeathSave(u8),
+    Stabilize,
+    Heal(u32),
+    Temp(u32),
+    ReduceMax(u32),
+    RestoreMax,
+    /// Max HP went up by levelling, and current HP with it
+    LevelUp(u32),
+    /// Max HP changed with CON or HP bonuses, and current HP with it
+    Recalculated {
+        before: u32,
+        after: u32,
+    },
+    /// A hit die spent on a short rest, healing `roll` plus the CON modifier
+    HitDie {
+        dice: Dice,
+        roll: u8,
+        con_mod: i8,
+    },
+    /// Back to full HP, regaining `hit_dice` spent hit dice
+    LongRest {
+        hit_dice: u32,
+    },
+}
+
+impl std::fmt::Display for HpEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HpEvent::Damage {
+                amount,
+                damage_type,
+                taken,
+                critical,
+            } => {
+                write!(f, "took {}", taken)?;
+                if let Some(damage_type) = damage_type {
+                    write!(f, " {}", damage_type.to_string().to_lowercase())?;
+                }
+                write!(f, " damage")?;
+                if taken != amount {
+                    write!(f, " ({} before defenses)", amount)?;
+                }
+                if *critical {
+                    write!(f, " from a critical hit")?;
+                }
+                Ok(())
+            }
+            HpEvent::DeathSave(roll) => write!(f, "rolled {} on a death save", roll),
+            HpEvent::Stabilize => write!(f, "stabilized"),
+            HpEvent::Heal(amount) => write!(f, "healed {}", amount),
+            HpEvent::Temp(amount) => write!(f, "gained {} temporary HP", amount),
+            HpEvent::ReduceMax(amount) => write!(f, "max HP reduced by {}", amount),
+            HpEvent::RestoreMax => write!(f, "max HP restored"),
+            HpEvent::LevelUp(amount) => write!(f, "levelled up, gaining {} max HP", amount),
+            HpEvent::Recalculated { before, after } => {
+                write!(f, "max HP recalculated from {} to {}", before, after)
+            }
+            HpEvent::HitDie {
+                dice,
+                roll,
+                con_mod,
+            } => write!(
+                f,
+                "spent a {} hit die, rolling {} {:+}",
+                dice, roll, con_mod
+            ),
+            HpEvent::LongRest { hit_dice } => {
+                write!(f, "took a long rest, regaining {} hit dice", hit_dice)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HpLogEntry {
+    pub timestamp: DateTime<Local>,
+    pub event: HpEvent,
+    /// Current HP after the event
+    pub current: u32,
+    pub temp: u32,
+}
+
+impl std::fmt::Display for HpLogEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {}: {} HP",
+            self.timestamp.format("%Y-%m-%d %H:%M"),
+            self.event,
+            self.current
+        )?;
+        if self.temp > 0 {
+            write!(f, " + {} temporary", self.temp)?;
+        }
+        Ok(())
+    }
+}
+
+/// Current HP at the table, saved on the character between sessions.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HpTracker {
+    pub current: u32,
+    pub temp: u32,
+    /// How much max HP is lowered by
+    pub max_reduction: u32,
+    #[serde(default)]
+    pub death_saves: DeathSaves,
+    /// Unconscious at 0 HP, but no longer rolling death saves
+    #[serde(default)]
+    pub stable: bool,
+    #[serde(default)]
+    pub dead: bool,
+    /// How many hit dice of each size, by number of faces, have been spent
+    #[serde(default)]
+    pub spent_hit_dice: BTreeMap<u8, u32>,
+    #[serde(default)]
+    pub log: Vec<HpLogEntry>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeathSaves {
+    pub successes: u8,
+    pub failures: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, Serialize)]
+pub enum Condition {
+    Conscious,
+    #[strum(serialize = "dying")]
+    Dying,
+    #[strum(serialize = "unconscious but stable")]
+    Stable,
+    #[strum(serialize = "dead")]
+    Dead,
+}
+
+impl HpTracker {
+    /// A character at full HP.
+    pub fn new(max: u32) -> Self {
+        Self {
+            current: max,
+            temp: 0,
+            max_reduction: 0,
+            death_saves: DeathSaves::default(),
+            stable: false,
+            dead: false,
+            spent_hit_dice: BTreeMap::new(),
+            log: vec![],
+        }
+    }
+
+    /// `max` after any reductions.
+    pub fn max(&self, max: u32) -> u32 {
+        max.saturating_sub(self.max_reduction)
+    }
+
+    pub fn is_unconscious(&self) -> bool {
+        self.current == 0
+    }
+
+    pub fn is_dying(&self) -> bool {
+        self.condition() == Condition::Dying
+    }
+
+    pub fn condition(&self) -> Condition {
+        match (self.dead, self.current, self.stable) {
+            (true, _, _) => Condition::Dead,
+            (false, 0, true) => Condition::Stable,
+            (false, 0, false) => Condition::Dying,
+            _ => Condition::Conscious,
+        }
+    }
+
+    fn fail_death_saves(&mut self, failures: u8) {
+        self.stable = false;
+        self.death_saves.failures += failures;
+        if self.death_saves.failures >= 3 {
+            self.dead = true;
+        }
+    }
+
+    fn heal(&mut self, max: u32, amount: u32) {
+        if self.is_unconscious() && amount > 0 {
+            self.stable = false;
+            self.reset_death_saves();
+        }
+        self.current = self.current.saturating_add(amount).min(self.max(max));
+    }
+
+    /// Regaining consciousness, or stabilizing, wipes the slate clean.
+    fn reset_death_saves(&mut self) {
+        self.death_saves = DeathSaves::default();
+    }
+
+    /// Applies `event` to a character whose max HP is `max` and logs it.
+    /// Nothing but the log changes once a character is dead.
+    pub fn apply(&mut self, max: u32, event: HpEvent) -> &HpLogEntry {
+        match event {
+            _ if self.dead => tracing::warn!("Nothing more can be done, they're dead"),
+            // defenses apply before temporary HP soaks anything up
+            HpEvent::Damage {
+                taken, critical, ..
+            } => {
+                let absorbed = taken.min(self.temp);
+                self.temp -= absorbed;
+                let damage = taken - absorbed;
+                // whatever is left after dropping to 0 counts towards massive damage
+                let overflow = damage.saturating_sub(self.current);
+                let was_unconscious = self.is_unconscious();
+                self.current = self.current.saturating_sub(damage);
+                if self.is_unconscious() && overflow >= self.max(max) && damage > 0 {
+                    self.dead = true;
+                } else if was_unconscious && damage > 0 {
+                    self.fail_death_saves(if critical { 2 } else { 1 });
+                }
+            }
+            HpEvent::DeathSave(_) if !self.is_dying() => {}
+            HpEvent::DeathSave(20) => {
+                self.current = 1;
+                self.reset_death_saves();
+            }
+            HpEvent::DeathSave(1) => self.fail_death_saves(2),
+            HpEvent::DeathSave(roll) if roll >= 10 => {
+                self.death_saves.successes += 1;
+                if self.death_saves.successes >= 3 {
+                    self.stable = true;
+                    self.reset_death_saves();
+                }
+            }
+            HpEvent::DeathSave(_) => self.fail_death_saves(1),
+            HpEvent::Stabilize => {
+                if self.is_dying() {
+                    self.stable = true;
+                    self.reset_death_saves();
+                }
+            }
+            HpEvent::Heal(amount) => self.heal(max, amount),
+            // temporary HP doesn't stack, you pick the better of the two
+            HpEvent::Temp(amount) => self.temp = self.temp.max(amount),
+            HpEvent::ReduceMax(amount) => {
+                self.max_reduction = self.max_reduction.saturating_add(amount).min(max);
+                self.current = self.current.min(self.max(max));
+                // a creature whose max HP drops to 0 dies
+                self.dead = self.max(max) == 0;
+            }
+            HpEvent::RestoreMax => self.max_reduction = 0,
+            HpEvent::LevelUp(amount) => {
+                self.current = self.current.saturating_add(amount).min(self.max(max));
+            }
+            HpEvent::Recalculated { before, after } => {
+                let gained = after.saturating_sub(before);
+                self.current = self.current.saturating_add(gained).min(self.max(max));
+            }
+            HpEvent::HitDie {
+                dice,
+                roll,
+                con_mod,
+            } => {
+                *self.spent_hit_dice.entry(u8::from(dice)).or_default() += 1;
+                self.heal(max, (roll as i32 + con_mod as i32).max(0) as u32);
+            }
+            HpEvent::LongRest { hit_dice } => {
+                // the biggest dice are the most useful, so they come back first
+                let mut regained = hit_dice;
+                for spent in self.spent_hit_dice.values_mut().rev() {
+                    let amount = regained.min(*spent);
+                    *spent -= amount;
+                    regained -= amount;
+                }
+                self.spent_hit_dice.retain(|_, spent| *spent > 0);
+                // most reductions to max HP last until a long rest
+                self.max_reduction = 0;
+                self.current = max;
+                self.temp = 0;
+                self.stable = false;
+                self.reset_death_saves();
+            }
+        }
+        self.log.push(HpLogEntry {
+            timestamp: Local::now(),
+            event,
+            current: self.current,
+            temp: self.temp,
+        });
+        let entry = &self.log[self.log.len() - 1];
+        tracing::info!("{}", entry);
+        entry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dnd::Defenses;
+    use pretty_assertions::assert_eq;
+    use std::collections::BTreeSet;
+
+    fn damage(amount: u32) -> HpEvent {
+        HpEvent::Damage {
+            amount,
+            damage_type: None,
+            taken: amount,
+            critical: false,
+        }
+    }
+
+    #[test_case]
+    fn test_tracker_damage_and_healing() -> TResult {
+        test(|| {
+            let mut tracker = HpTracker::new(20);
+            tracker.apply(20, HpEvent::Temp(5));
+            // the lower temporary HP is ignored rather than added
+            tracker.apply(20, HpEvent::Temp(3));
+            tracker.apply(20, damage(8));
+            assert_eq!((tracker.current, tracker.temp), (17, 0));
+            tracker.apply(20, HpEvent::Heal(10));
+            assert_eq!(tracker.current, 20);
+            tracker.apply(20, HpEvent::ReduceMax(6));
+            assert_eq!((tracker.current, tracker.max(20)), (14, 14));
+            tracker.apply(20, HpEvent::Heal(10));
+            assert_eq!(tracker.current, 14);
+            tracker.apply(20, damage(20));
+            assert!(tracker.is_unconscious());
+            tracker.apply(20, HpEvent::RestoreMax);
+            tracker.apply(20, HpEvent::Heal(25));
+            tracker.apply(20, HpEvent::Heal(u32::MAX));
+            assert_eq!(tracker.current, 20);
+            // an Amulet of Health adds to current HP too, losing it only caps it
+            tracker.apply(
+                25,
+                HpEvent::Recalculated {
+                    before: 20,
+                    after: 25,
+                },
+            );
+            assert_eq!(tracker.current, 25);
+            tracker.apply(
+                15,
+                HpEvent::Recalculated {
+                    before: 25,
+                    after: 15,
+                },
+            );
+            assert_eq!(tracker.current, 15);
+            assert_eq!(tracker.log.len(), 12);
+            tracker.current
+        })
+    }
+
+    #[test_case]
+    fn test_tracker_defenses_before_temp_hp() -> TResult {
+        test(|| {
+            let defenses = Defenses {
+                resistances: BTreeSet::from([DamageType::Fire]),
+                ..Default::default()
+            };
+            let mut tracker = HpTracker::new(30);
+            tracker.apply(30, HpEvent::Temp(5));
+            // 21 fire is halved to 10 first, then the temporary HP takes 5 of it
+            let taken = defenses.adjust(21, Some(DamageType::Fire));
+            tracker.apply(
+                30,
+                HpEvent::Damage {
+                    amount: 21,
+                    damage_type: Some(DamageType::Fire),
+                    taken,
+                    critical: false,
+                },
+            );
+            assert_eq!((tracker.current, tracker.temp), (25, 0));
+            tracker.log.last().unwrap().to_string()
+        })
+    }
+
+    #[test_case]
+    fn test_death_saves() -> TResult {
+        test(|| {
+            let mut tracker = HpTracker::new(20);
+            tracker.apply(20, damage(25));
+            assert!(tracker.is_dying());
+            tracker.apply(20, HpEvent::DeathSave(12));
+            tracker.apply(20, HpEvent::DeathSave(1));
+            assert_eq!(
+                tracker.death_saves,
+                DeathSaves {
+                    successes: 1,
+                    failures: 2
+                }
+            );
+            // a natural 20 gets you back up
+            tracker.apply(20, HpEvent::DeathSave(20));
+            assert_eq!(
+                (tracker.current, tracker.condition()),
+                (1, Condition::Conscious)
+            );
+            assert_eq!(tracker.death_saves, DeathSaves::default());
+
+            tracker.apply(20, damage(1));
+            tracker.apply(20, HpEvent::DeathSave(10));
+            tracker.apply(20, HpEvent::DeathSave(15));
+            tracker.apply(20, HpEvent::DeathSave(19));
+            assert_eq!(tracker.condition(), Condition::Stable);
+            // a critical hit while down counts as two failures, on top of a failed save
+            tracker.apply(
+                20,
+                HpEvent::Damage {
+                    amount: 3,
+                    damage_type: None,
+                    taken: 3,
+                    critical: true,
+                },
+            );
+            assert_eq!(tracker.death_saves.failures, 2);
+            tracker.apply(20, HpEvent::DeathSave(9));
+            assert_eq!(tracker.condition(), Condition::Dead);
+            tracker.apply(20, HpEvent::Heal(10));
+            assert_eq!(tracker.current, 0);
+            tracker.condition()
+        })
+    }
+
+    #[test_case]
+    fn test_massive_damage() -> TResult {
+        test(|| {
+            let mut tracker = HpTracker::new(12);
+            tracker.apply(12, HpEvent::Temp(5));
+            // 5 absorbed, 12 to drop to 0, 11 left over is less than max
+            tracker.apply(12, damage(28));
+            assert_eq!(tracker.condition(), Condition::Dying);
+            tracker.apply(12, HpEvent::Heal(2));
+            // 2 to drop to 0, 12 left over
+            tracker.apply(12, damage(14));
+            assert_eq!(tracker.condition(), Condition::Dead);
+
+            let mut tracker = HpTracker::new(12);
+            tracker.apply(12, damage(12));
+            // at 0 HP a single hit of max HP or more is also fatal
+            tracker.apply(12, damage(12));
+            assert_eq!(tracker.condition(), Condition::Dead);
+            tracker.condition()
+        })
+    }
+}
@@ -1,33 +1,48 @@
-#![feature(panic_info_message)]
 #![feature(custom_test_frameworks)]
 #![test_runner(crate::testing::test_runner)]
 
 use std::fmt::Display;
 
+use clap::{Parser, Subcommand};
 use crossterm::{execute, terminal::ClearType};
 use strum::{EnumIter, IntoEnumIterator};
-use tracing::Subscriber;
-use tracing_subscriber::{filter::filter_fn, Layer};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 
-use crate::prelude::select;
+use crate::{ctx::Ctx, prelude::select};
 
+mod abilities;
 mod ac;
+mod character;
+mod ctx;
 mod dnd;
+mod history;
 mod hp;
 mod prelude;
+mod probability;
+mod roll;
+#[cfg(test)]
 mod testing;
 
+/// Logs go to stdout, unless stdout is reserved for `--json` output.
+fn writer(json: bool) -> BoxMakeWriter {
+    match json {
+        true => BoxMakeWriter::new(std::io::stderr),
+        false => BoxMakeWriter::new(std::io::stdout),
+    }
+}
+
 #[cfg(debug_assertions)]
-fn subscriber() -> impl tracing::Subscriber {
-    tracing_subscriber::FmtSubscriber::default()
+fn subscriber(json: bool) -> impl tracing::Subscriber {
+    tracing_subscriber::fmt().with_writer(writer(json)).finish()
 }
 
 #[cfg(not(debug_assertions))]
-fn subscriber() -> impl tracing::Subscriber {
-    use tracing_subscriber::layer::SubscriberExt;
+fn subscriber(json: bool) -> impl tracing::Subscriber {
+    use tracing_subscriber::{filter::filter_fn, layer::SubscriberExt, Layer};
 
     tracing_subscriber::registry().with(
         tracing_subscriber::fmt::layer()
+            .with_writer(writer(json))
             .with_target(false)
             .with_ansi(false)
             .pretty()
@@ -41,17 +56,88 @@ fn subscriber() -> impl tracing::Subscriber {
     )
 }
 
+/// A mystical CLI app to help you in your Dungeons And Dragons pilgrimage.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Args {
+    /// Seed the dice roller so rolls can be reproduced
+    #[arg(long, global = true)]
+    seed: Option<u64>,
+    /// Print results as JSON instead of prose
+    #[arg(long, global = true)]
+    json: bool,
+    /// Run a single tool instead of opening the menu
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Roll dice expressions
+    Roll(roll::RollArgs),
+    /// Calculate max HP
+    Hp(hp::HpArgs),
+    /// Level up a saved character
+    LevelUp(hp::LevelUpArgs),
+    /// Update a saved character's max HP after their CON or HP bonuses change
+    Recalculate(hp::RecalculateArgs),
+    /// Track a saved character's current HP
+    Track(hp::TrackArgs),
+    /// Take a short or long rest with a saved character
+    Rest(hp::RestArgs),
+    /// Calculate AC
+    Ac(ac::AcArgs),
+    /// Show the odds of a dice expression
+    Odds(probability::OddsArgs),
+    /// Browse, filter and export the roll history
+    History(history::HistoryArgs),
+    /// Generate ability scores
+    Abilities(abilities::AbilitiesArgs),
+    /// Create, list, show and delete saved characters
+    Character(character::CharacterArgs),
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tracing::subscriber::set_global_default(subscriber())?;
+    let args = Args::parse();
+    tracing::subscriber::set_global_default(subscriber(args.json))?;
 
-    let tool = select("What would you like to do?", Tool::iter().collect());
+    tracing::debug!(?args);
+    let mut ctx = Ctx::new(args.seed, args.json);
 
-    tracing::debug!(?tool);
+    if let Some(command) = args.command {
+        match command {
+            Command::Roll(args) => roll::roll_dice(&mut ctx, args)?,
+            Command::Hp(args) => hp::calculate_hp(&mut ctx, args)?,
+            Command::LevelUp(args) => hp::level_up(&mut ctx, args)?,
+            Command::Recalculate(args) => hp::recalculate_hp(&mut ctx, args)?,
+            Command::Track(args) => hp::track_hp(&mut ctx, args)?,
+            Command::Rest(args) => hp::rest(&mut ctx, args)?,
+            Command::Ac(args) => ac::calculate_ac(&mut ctx, args)?,
+            Command::Odds(args) => probability::calculate_odds(&mut ctx, args)?,
+            Command::History(args) => history::roll_history(&mut ctx, args)?,
+            Command::Abilities(args) => abilities::generate_abilities(&mut ctx, args)?,
+            Command::Character(args) => character::manage_characters(&mut ctx, args)?,
+        }
+        return Ok(());
+    }
 
     loop {
+        let tool = select("What would you like to do?", Tool::iter().collect());
+
+        tracing::debug!(?tool);
+
         match tool {
-            Tool::CalculateHp => hp::calculate_hp()?,
-            Tool::CalculateAc => ac::calculate_ac()?,
+            Tool::RollDice => roll::roll_dice(&mut ctx, Default::default())?,
+            Tool::CalculateHp => hp::calculate_hp(&mut ctx, Default::default())?,
+            Tool::LevelUp => hp::level_up(&mut ctx, Default::default())?,
+            Tool::Recalculate => hp::recalculate_hp(&mut ctx, Default::default())?,
+            Tool::TrackHp => hp::track_hp(&mut ctx, Default::default())?,
+            Tool::Rest => hp::rest(&mut ctx, Default::default())?,
+            Tool::CalculateAc => ac::calculate_ac(&mut ctx, Default::default())?,
+            Tool::DiceOdds => probability::calculate_odds(&mut ctx, Default::default())?,
+            Tool::RollHistory => history::roll_history(&mut ctx, Default::default())?,
+            Tool::GenerateAbilities => abilities::generate_abilities(&mut ctx, Default::default())?,
+            Tool::Characters => character::manage_characters(&mut ctx, Default::default())?,
         }
         let again = select(
             "What shall be your next destination?",
@@ -72,15 +158,33 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
 #[derive(Debug, EnumIter, Clone)]
 enum Tool {
+    RollDice,
     CalculateHp,
+    LevelUp,
+    Recalculate,
+    TrackHp,
+    Rest,
     CalculateAc,
+    DiceOdds,
+    RollHistory,
+    GenerateAbilities,
+    Characters,
 }
 
 impl Display for Tool {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let string = match self {
+            Tool::RollDice => "Roll dice".to_string(),
             Tool::CalculateHp => "Calculate HP".to_string(),
+            Tool::LevelUp => "Level up".to_string(),
+            Tool::Recalculate => "Update CON or HP bonuses".to_string(),
+            Tool::TrackHp => "Track HP".to_string(),
+            Tool::Rest => "Take a rest".to_string(),
             Tool::CalculateAc => "Calculate AC".to_string(),
+            Tool::DiceOdds => "Calculate dice odds".to_string(),
+            Tool::RollHistory => "Roll history".to_string(),
+            Tool::GenerateAbilities => "Generate ability scores".to_string(),
+            Tool::Characters => "Saved characters".to_string(),
         };
         write!(f, "{}", string)
     }
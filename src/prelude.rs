@@ -1,12 +1,9 @@
-use std::{fmt::Display, process::exit, str::FromStr, sync::Arc};
+use std::{fmt::Display, process::exit, sync::Arc};
 
-use inquire::{InquireError, Select};
+use inquire::{InquireError, MultiSelect, Select};
+use strum::IntoEnumIterator;
 use yansi::Paint;
 
-/// newtype pattern
-#[derive(Clone)]
-pub struct W<T>(pub T);
-
 fn or_cancel<T>(res: Result<T, InquireError>) -> anyhow::Result<T> {
     res.map_err(|err| match err {
         InquireError::OperationCanceled | InquireError::OperationInterrupted => {
@@ -37,6 +34,16 @@ pub fn select<T: Display + Clone>(prompt: &str, opts: Vec<T>) -> T {
     })
 }
 
+pub fn try_multi_select<T: Display>(prompt: &str, opts: Vec<T>) -> anyhow::Result<Vec<T>> {
+    or_cancel(MultiSelect::new(prompt, opts).prompt())
+}
+
+pub fn multi_select<T: Display + Clone>(prompt: &str, opts: Vec<T>) -> Vec<T> {
+    or_retry(try_multi_select(prompt, opts.clone()), || {
+        multi_select(prompt, opts.clone())
+    })
+}
+
 pub fn try_input(prompt: &str) -> anyhow::Result<String> {
     or_cancel(inquire::Text::new(prompt).prompt())
 }
@@ -50,6 +57,20 @@ pub fn input_map<T, Res: IntoAnyhow<T>>(prompt: &str, f: impl Fn(&str) -> Res +
     or_retry(try_input(), || input_map(prompt, f.clone()))
 }
 
+/// Like [`input_map`], but an empty answer returns `None` instead of being parsed.
+pub fn input_map_opt<T, Res: IntoAnyhow<T>>(
+    prompt: &str,
+    f: impl Fn(&str) -> Res + Clone,
+) -> Option<T> {
+    let try_input = || {
+        try_input(prompt).and_then(|s| match s.trim() {
+            "" => Ok(None),
+            s => f(s).anyhow().map(Some),
+        })
+    };
+    or_retry(try_input(), || input_map_opt(prompt, f.clone()))
+}
+
 pub fn try_confirm(prompt: &str) -> anyhow::Result<bool> {
     or_cancel(
         inquire::Confirm::new(prompt)
@@ -64,22 +85,21 @@ pub fn confirm(prompt: &str) -> bool {
     or_retry(try_confirm(prompt), || confirm(prompt))
 }
 
-pub fn arc_str(string: String) -> Arc<str> {
-    Arc::from(string)
-}
-
-impl FromStr for W<Arc<str>> {
-    type Err = anyhow::Error;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(W(Arc::from(s)))
-    }
+/// Finds the variant whose display name matches `name`, ignoring case,
+/// spaces, dashes and underscores, so `studded-leather` finds `Studded leather`.
+pub fn from_name<T: IntoEnumIterator + Display>(name: &str) -> Option<T> {
+    let normalize = |s: &str| {
+        s.chars()
+            .filter(|c| !matches!(c, ' ' | '-' | '_'))
+            .flat_map(char::to_lowercase)
+            .collect::<String>()
+    };
+    let name = normalize(name);
+    T::iter().find(|variant| normalize(&variant.to_string()) == name)
 }
 
-impl ToString for W<Arc<str>> {
-    fn to_string(&self) -> String {
-        self.0.to_string()
-    }
+pub fn arc_str(string: String) -> Arc<str> {
+    Arc::from(string)
 }
 
 pub trait IntoAnyhow<T> {
@@ -92,5 +112,7 @@ impl<T, E: std::error::Error + Send + Sync + 'static> IntoAnyhow<T> for Result<T
     }
 }
 
+#[cfg(test)]
 pub use crate::testing::test;
+#[cfg(test)]
 pub use crate::testing::TResult;
@@ -0,0 +1,428 @@
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::ctx::Ctx;
+use crate::dnd::{DiceExpr, DicePool, Keep, MAX_EXPLOSIONS};
+
+use crate::prelude::*;
+
+/// Probability mass below which an exploding die stops being followed.
+const EXPLOSION_EPSILON: f64 = 1e-12;
+
+/// Upper bound on the number of distinct totals tracked, so something like
+/// `1000d100` gives up instead of grinding for minutes.
+const MAX_OUTCOMES: usize = 20_000;
+
+const HISTOGRAM_WIDTH: f64 = 50.0;
+const HISTOGRAM_ROWS: i32 = 40;
+
+#[derive(Debug, Default, clap::Args)]
+pub struct OddsArgs {
+    /// The dice expression to analyse, e.g. `2d6+3`
+    expr: Option<DiceExpr>,
+    /// Show the chance to meet or beat this total
+    #[arg(long)]
+    target: Option<i32>,
+}
+
+#[derive(Debug, Serialize)]
+struct OddsReport {
+    expression: String,
+    mean: f64,
+    variance: f64,
+    percentiles: BTreeMap<u8, i32>,
+    distribution: Distribution,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target: Option<Target>,
+}
+
+#[derive(Debug, Serialize)]
+struct Target {
+    target: i32,
+    chance: f64,
+}
+
+impl Target {
+    fn describe(&self) {
+        tracing::info!(
+            "Chance to roll {} or higher: {:.2}%",
+            self.target,
+            self.chance * 100.0
+        );
+    }
+}
+
+pub fn calculate_odds(ctx: &mut Ctx, args: OddsArgs) -> anyhow::Result<()> {
+    let complete = args.expr.is_some();
+    let expr = args
+        .expr
+        .unwrap_or_else(|| input_map("Dice expression: ", DiceExpr::from_str));
+    let distribution = Distribution::of(&expr)?;
+
+    tracing::debug!(?expr, ?distribution);
+
+    let target = args.target.map(|target| Target {
+        target,
+        chance: distribution.chance_at_least(target),
+    });
+    let report = OddsReport {
+        expression: expr.to_string(),
+        mean: distribution.mean(),
+        variance: distribution.variance(),
+        percentiles: [10, 25, 50, 75, 90]
+            .into_iter()
+            .map(|p| (p, distribution.percentile(p as f64 / 100.0)))
+            .collect(),
+        distribution,
+        target,
+    };
+    ctx.output(&report, |report| {
+        println!("{}", report.distribution.histogram());
+        tracing::info!("Mean: {:.2}", report.mean);
+        tracing::info!(
+            "Variance: {:.2} (standard deviation {:.2})",
+            report.variance,
+            report.variance.sqrt()
+        );
+        let percentiles = report
+            .percentiles
+            .iter()
+            .map(|(p, value)| format!("{}th: {}", p, value))
+            .collect::<Vec<_>>();
+        tracing::info!("Percentiles: {}", percentiles.join(", "));
+        if let Some(target) = &report.target {
+            target.describe();
+        }
+    })?;
+
+    if report.target.is_none() && !complete && !ctx.json {
+        let target = input_map_opt(
+            "Target to meet or beat (leave empty to skip): ",
+            str::parse::<i32>,
+        );
+        if let Some(target) = target {
+            let chance = report.distribution.chance_at_least(target);
+            Target { target, chance }.describe();
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum UnsupportedDistribution {
+    ExplodeAndKeep,
+    AlwaysExplodes,
+    TooManyOutcomes,
+    Overflow,
+}
+
+impl std::fmt::Display for UnsupportedDistribution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UnsupportedDistribution::ExplodeAndKeep => write!(
+                f,
+                "odds can't be calculated for exploding dice combined with keep or drop"
+            ),
+            UnsupportedDistribution::AlwaysExplodes => {
+                write!(f, "odds can't be calculated for dice that always explode")
+            }
+            UnsupportedDistribution::TooManyOutcomes => write!(
+                f,
+                "odds can't be calculated for more than {} different totals",
+                MAX_OUTCOMES
+            ),
+            UnsupportedDistribution::Overflow => {
+                write!(f, "odds can't be calculated for totals this large")
+            }
+        }
+    }
+}
+
+impl std::error::Error for UnsupportedDistribution {}
+
+/// Exact probability distribution of the totals a dice expression can roll.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Distribution(BTreeMap<i32, f64>);
+
+impl Distribution {
+    pub fn constant(value: i32) -> Self {
+        Distribution(BTreeMap::from([(value, 1.0)]))
+    }
+
+    pub fn of(expr: &DiceExpr) -> Result<Self, UnsupportedDistribution> {
+        Ok(match expr {
+            DiceExpr::Roll(pool) => Distribution::of_pool(pool)?,
+            DiceExpr::Flat(value) => Distribution::constant(*value),
+            DiceExpr::Neg(expr) => {
+                Distribution::of(expr)?.combine(&Distribution::constant(-1), i32::checked_mul)?
+            }
+            DiceExpr::Add(lhs, rhs) => {
+                Distribution::of(lhs)?.combine(&Distribution::of(rhs)?, i32::checked_add)?
+            }
+            DiceExpr::Sub(lhs, rhs) => {
+                Distribution::of(lhs)?.combine(&Distribution::of(rhs)?, i32::checked_sub)?
+            }
+            DiceExpr::Mul(lhs, rhs) => {
+                Distribution::of(lhs)?.combine(&Distribution::of(rhs)?, i32::checked_mul)?
+            }
+        })
+    }
+
+    /// Distribution of a single die of the pool once reroll, minimum and
+    /// explode modifiers have been applied.
+    fn of_die(pool: &DicePool) -> Result<Distribution, UnsupportedDistribution> {
+        let faces = i32::from(u8::from(pool.dice));
+        let p = 1.0 / faces as f64;
+        let mut die = (1..=faces)
+            .map(|value| (value, p))
+            .collect::<BTreeMap<_, _>>();
+
+        if let Some(at_most) = pool.reroll {
+            let reroll_chance = i32::from(at_most) as f64 * p;
+            for (value, chance) in die.iter_mut() {
+                let kept = if *value <= i32::from(at_most) { 0.0 } else { p };
+                *chance = kept + reroll_chance * p;
+            }
+        }
+        if let Some(minimum) = pool.minimum {
+            let minimum = i32::from(minimum);
+            let raised = die.range(..minimum).map(|(_, chance)| chance).sum::<f64>();
+            die.retain(|value, _| *value >= minimum);
+            *die.entry(minimum).or_default() += raised;
+        }
+        let mut die = Distribution(die);
+
+        // like the roller, a d1 never explodes
+        if pool.explode && faces > 1 {
+            // A chain of maximum rolls followed by a final non-maximum roll.
+            let max_chance = die.0[&faces];
+            if max_chance >= 1.0 {
+                return Err(UnsupportedDistribution::AlwaysExplodes);
+            }
+            let rest = Distribution(die.0.range(..faces).map(|(v, c)| (*v, *c)).collect());
+            let mut chain = rest.clone();
+            let mut continuing = max_chance;
+            let mut depth = 1;
+            while continuing > EXPLOSION_EPSILON && depth <= MAX_EXPLOSIONS as i32 {
+                let shifted = rest
+                    .0
+                    .iter()
+                    .map(|(value, chance)| (value + faces * depth, chance * continuing));
+                for (value, chance) in shifted {
+                    *chain.0.entry(value).or_default() += chance;
+                }
+                continuing *= max_chance;
+                depth += 1;
+            }
+            die = chain;
+        }
+        Ok(die)
+    }
+
+    fn of_pool(pool: &DicePool) -> Result<Distribution, UnsupportedDistribution> {
+        let die = Distribution::of_die(pool)?;
+        if pool.count as usize * die.0.len() > MAX_OUTCOMES {
+            return Err(UnsupportedDistribution::TooManyOutcomes);
+        }
+        let Some(keep) = pool.keep else {
+            return (0..pool.count).try_fold(Distribution::constant(0), |total, _| {
+                total.combine(&die, i32::checked_add)
+            });
+        };
+        if pool.explode {
+            return Err(UnsupportedDistribution::ExplodeAndKeep);
+        }
+
+        // Walk the faces from the ones we keep first, deciding how many dice
+        // show each face; the first `n` dice assigned are the kept ones.
+        let (faces, n): (Vec<_>, _) = match keep {
+            Keep::Highest(n) => (die.0.iter().rev().collect(), n),
+            Keep::Lowest(n) => (die.0.iter().collect(), n),
+        };
+        let count = pool.count;
+        let mut states = HashMap::from([((0u32, 0i32), 1.0)]);
+        for (&value, &p) in faces {
+            let mut next = HashMap::new();
+            for ((assigned, sum), chance) in states {
+                let remaining = count - assigned;
+                let mut weight = 1.0;
+                for j in 0..=remaining {
+                    if j > 0 {
+                        weight *= (remaining - j + 1) as f64 / j as f64 * p;
+                    }
+                    let kept = j.min(n.saturating_sub(assigned));
+                    let key = (assigned + j, sum + value * kept as i32);
+                    *next.entry(key).or_default() += chance * weight;
+                }
+            }
+            if next.len() > MAX_OUTCOMES {
+                return Err(UnsupportedDistribution::TooManyOutcomes);
+            }
+            states = next;
+        }
+
+        let mut totals = BTreeMap::new();
+        for ((assigned, sum), chance) in states {
+            if assigned == count {
+                *totals.entry(sum).or_default() += chance;
+            }
+        }
+        Ok(Distribution(totals))
+    }
+
+    /// Distribution of `op` applied to independent rolls of both sides,
+    /// failing once a total overflows or there are too many to track.
+    fn combine(
+        &self,
+        other: &Distribution,
+        op: impl Fn(i32, i32) -> Option<i32>,
+    ) -> Result<Distribution, UnsupportedDistribution> {
+        let mut result = BTreeMap::new();
+        for (a, pa) in &self.0 {
+            for (b, pb) in &other.0 {
+                let total = op(*a, *b).ok_or(UnsupportedDistribution::Overflow)?;
+                *result.entry(total).or_default() += pa * pb;
+            }
+            if result.len() > MAX_OUTCOMES {
+                return Err(UnsupportedDistribution::TooManyOutcomes);
+            }
+        }
+        Ok(Distribution(result))
+    }
+
+    #[cfg(test)]
+    pub fn chance_of(&self, value: i32) -> f64 {
+        self.0.get(&value).copied().unwrap_or(0.0)
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.0.iter().map(|(value, p)| *value as f64 * p).sum()
+    }
+
+    pub fn variance(&self) -> f64 {
+        let mean = self.mean();
+        self.0
+            .iter()
+            .map(|(value, p)| (*value as f64 - mean).powi(2) * p)
+            .sum()
+    }
+
+    /// Smallest total that is rolled with at least `p` cumulative probability.
+    pub fn percentile(&self, p: f64) -> i32 {
+        let mut cumulative = 0.0;
+        for (value, chance) in &self.0 {
+            cumulative += chance;
+            if cumulative + f64::EPSILON >= p {
+                return *value;
+            }
+        }
+        *self.0.keys().next_back().unwrap_or(&0)
+    }
+
+    pub fn chance_at_least(&self, target: i32) -> f64 {
+        self.0.range(target..).map(|(_, p)| p).sum()
+    }
+
+    pub fn histogram(&self) -> String {
+        let (Some(&min), Some(&max)) = (self.0.keys().next(), self.0.keys().next_back()) else {
+            return String::new();
+        };
+        // the span of an i32 range only fits in an i64
+        let (min, max) = (i64::from(min), i64::from(max));
+        let bucket = ((max - min + 1) as f64 / HISTOGRAM_ROWS as f64)
+            .ceil()
+            .max(1.0) as i64;
+        let rows = (0..)
+            .map(|row| min + row * bucket)
+            .take_while(|start| *start <= max)
+            .map(|start| {
+                let end = (start + bucket - 1).min(max);
+                // both ends lie between two totals, so they fit back in an i32
+                let chance = self
+                    .0
+                    .range(start as i32..=end as i32)
+                    // an empty sum of floats is -0.0
+                    .fold(0.0, |sum, (_, chance)| sum + chance);
+                let label = match start == end {
+                    true => format!("{}", start),
+                    false => format!("{}-{}", start, end),
+                };
+                (label, chance)
+            })
+            .collect::<Vec<_>>();
+        let peak = rows.iter().map(|(_, p)| *p).fold(0.0, f64::max);
+        let label_width = rows.iter().map(|(label, _)| label.len()).max().unwrap_or(0);
+        rows.into_iter()
+            .map(|(label, chance)| {
+                let bar = "#".repeat((chance / peak * HISTOGRAM_WIDTH).round() as usize);
+                format!(
+                    "{:>label_width$} | {:<50} {:.2}%",
+                    label,
+                    bar,
+                    chance * 100.0
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test_case]
+    fn test_distribution_2d6() -> TResult {
+        test(|| -> anyhow::Result<f64> {
+            let distribution = Distribution::of(&DiceExpr::from_str("2d6 + 3")?)?;
+            assert!(close(distribution.chance_of(10), 6.0 / 36.0));
+            assert!(close(distribution.mean(), 10.0));
+            assert!(close(distribution.variance(), 35.0 / 6.0));
+            assert_eq!(distribution.percentile(0.5), 10);
+            assert!(close(distribution.chance_at_least(13), 6.0 / 36.0));
+            Ok(distribution.mean())
+        })
+    }
+
+    #[test_case]
+    fn test_distribution_keep() -> TResult {
+        test(|| -> anyhow::Result<f64> {
+            let advantage = Distribution::of(&DiceExpr::from_str("d20 adv")?)?;
+            assert!(close(advantage.chance_at_least(11), 0.75));
+            let disadvantage = Distribution::of(&DiceExpr::from_str("d20 dis")?)?;
+            assert!(close(disadvantage.chance_at_least(11), 0.25));
+            let stats = Distribution::of(&DiceExpr::from_str("4d6kh3")?)?;
+            assert!(close(stats.chance_of(18), 21.0 / 1296.0));
+            assert!(close(stats.mean(), 15869.0 / 1296.0));
+            Ok(stats.mean())
+        })
+    }
+
+    #[test_case]
+    fn test_distribution_modifiers() -> TResult {
+        test(|| -> anyhow::Result<f64> {
+            let great_weapon = Distribution::of(&DiceExpr::from_str("2d6r<=2")?)?;
+            assert!(close(great_weapon.mean(), 2.0 * 25.0 / 6.0));
+            let minimum = Distribution::of(&DiceExpr::from_str("d6min2")?)?;
+            assert!(close(minimum.chance_of(2), 2.0 / 6.0));
+            let exploding = Distribution::of(&DiceExpr::from_str("d6!")?)?;
+            assert!(close(exploding.mean(), 4.2));
+            assert!(Distribution::of(&DiceExpr::from_str("4d6!kh3")?).is_err());
+            assert!(Distribution::of(&DiceExpr::from_str("d6 * 2147483647 * 2")?).is_err());
+            assert!(Distribution::of(&DiceExpr::from_str("1000d100")?).is_err());
+            // the histogram spans every i32 without overflowing
+            let wide = "(d2-1)*2147483647 - 2147483647 + (d2-1)*2147483647";
+            let histogram = Distribution::of(&DiceExpr::from_str(wide)?)?.histogram();
+            assert_eq!(histogram.lines().count(), HISTOGRAM_ROWS as usize);
+            Ok(exploding.mean())
+        })
+    }
+}
@@ -0,0 +1,113 @@
+use std::str::FromStr;
+
+use serde::Serialize;
+use yansi::{Color, Paint};
+
+use crate::ctx::Ctx;
+use crate::dnd::{DiceExpr, DieRoll, RollEvent, RollResult};
+use crate::history::RollRecord;
+
+use crate::prelude::*;
+
+#[derive(Debug, Default, clap::Args)]
+pub struct RollArgs {
+    /// Dice expressions to roll, e.g. `2d6+3` or `d20 adv`
+    exprs: Vec<DiceExpr>,
+    /// Label to file the rolls under in the history
+    #[arg(long)]
+    label: Option<String>,
+}
+
+pub fn roll_dice(ctx: &mut Ctx, args: RollArgs) -> anyhow::Result<()> {
+    let default_label = args.label.as_deref().unwrap_or("Dice roller");
+    if !args.exprs.is_empty() {
+        for expr in &args.exprs {
+            roll(ctx, expr, default_label)?;
+        }
+        return Ok(());
+    }
+
+    // `1d20 + 5 # perception` labels the roll in the history
+    while let Some((expr, label)) =
+        input_map_opt("Dice expression (leave empty to go back): ", |s| {
+            let (expr, label) = s.split_once('#').unwrap_or((s, default_label));
+            DiceExpr::from_str(expr).map(|expr| (expr, label.trim().to_string()))
+        })
+    {
+        roll(ctx, &expr, &label)?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+struct RollReport<'a> {
+    expression: String,
+    label: &'a str,
+    #[serde(flatten)]
+    result: RollResult,
+}
+
+fn roll(ctx: &mut Ctx, expr: &DiceExpr, label: &str) -> anyhow::Result<()> {
+    let result = expr.roll(&mut ctx.rng);
+    tracing::debug!(?expr, ?result);
+
+    ctx.record(RollRecord::from_roll(label, expr, &result));
+    let report = RollReport {
+        expression: expr.to_string(),
+        label,
+        result,
+    };
+    ctx.output(&report, |report| {
+        println!("{}", format_roll(expr, &report.result))
+    })
+}
+
+fn format_roll(expr: &DiceExpr, result: &RollResult) -> String {
+    let dice = result
+        .dice
+        .iter()
+        .map(format_die)
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!(
+        "{} {} = {}",
+        Paint::new(expr).dimmed(),
+        dice,
+        Paint::new(result.total).bold()
+    )
+}
+
+fn format_die(die: &DieRoll) -> String {
+    let faces = u8::from(die.dice);
+    let value = Paint::new(die.value);
+    let value = match (faces, die.value) {
+        (20, 20) => value.fg(Color::Black).bg(Color::Green).bold(),
+        (20, 1) => value.fg(Color::Black).bg(Color::Red).bold(),
+        (_, value) if u32::from(value) * 3 <= u32::from(faces) => Paint::red(die.value),
+        (_, value) if u32::from(value) * 3 <= u32::from(faces) * 2 => Paint::yellow(die.value),
+        _ => Paint::green(die.value),
+    };
+    let value = match die.dropped {
+        true => value.strikethrough().dimmed(),
+        false => value,
+    };
+
+    let mut formatted = String::new();
+    for event in &die.log {
+        match event {
+            RollEvent::Rerolled { from } => {
+                formatted += &Paint::new(format!("{}→", from)).dimmed().to_string()
+            }
+            RollEvent::Raised { from } => {
+                formatted += &Paint::new(format!("{}↑", from)).dimmed().to_string()
+            }
+            RollEvent::Exploded => {}
+        }
+    }
+    formatted += &value.to_string();
+    if die.log.contains(&RollEvent::Exploded) {
+        formatted += &Paint::new("!").bold().to_string();
+    }
+    formatted
+}
@@ -27,9 +27,8 @@ impl Write for InnerTestWriter {
     }
 }
 
-#[cfg(test)]
 pub fn test_runner(tests: &[&dyn Fn() -> TResult]) {
-    use tiny_gradient::{Gradient, GradientStr, RGB};
+    use tiny_gradient::{GradientStr, RGB};
 
     use tracing::span;
     use tracing_subscriber::{layer::SubscriberExt, Layer};